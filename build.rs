@@ -1,11 +1,7 @@
 use cmake::Config;
 
 fn main() {
-    // let dastrap_dst = Config::new(".")
-    //     .profile("RelWithDebInfo")
-    //     .build_target("libDaStrap")
-    //     .build();
-    // println!("cargo:rerun-if-changed=src/interop/extended");
+    println!("cargo:rerun-if-changed=src/interop/extended");
     println!("cargo:rerun-if-changed=libs/daScript");
 
     macro_rules! add_search_path {
@@ -41,16 +37,22 @@ fn main() {
         .build_target("libDaScript")
         .build();
 
+    // the C++ shims for what daScriptC.h does not expose
+    let dastrap_dst = Config::new(".")
+        .profile("RelWithDebInfo")
+        .build_target("libDaStrap")
+        .build();
+
+    add_search_path!(&dastrap_dst.join("lib"));
+    add_search_path!(&dastrap_dst.join("build/Release"));
+    add_search_path!(&dastrap_dst.join("build/RelWithDebInfo"));
+    add_search_path!(&dastrap_dst.join("build"));
+
     add_search_path!(&dascript_dst.join("build/Release"));
     add_search_path!(&dascript_dst.join("build/RelWithDebInfo"));
     add_search_path!(&dascript_dst.join("build"));
 
+    // libDaStrap depends on libDaScript, keep it first on the link line
+    println!("cargo:rustc-link-lib=static=libDaStrap");
     println!("cargo:rustc-link-lib=static=libDaScript");
-
-    // add_search_path!(&dastrap_dst.join("lib"));
-    // add_search_path!(&dastrap_dst.join("build/Release"));
-    // add_search_path!(&dastrap_dst.join("build/RelWithDebInfo"));
-    // add_search_path!(&dastrap_dst.join("build"));
-
-    // println!("cargo:rustc-link-lib=static=libDaStrap");
 }
@@ -0,0 +1,236 @@
+//! Derives for dastrap
+//!
+//! The generated code talks to `::dastrap::interop`, so it only expands inside
+//! crates depending on `dastrap`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, LitStr};
+
+/// Bind a `#[repr(C)]` struct as a daScript structure.
+///
+/// - `#[das(name = "...")]` on the struct renames it on the script side
+/// - `#[das(skip)]` on a field hides it from scripts, the layout is unchanged
+#[proc_macro_derive(DasStructure, attributes(das))]
+pub fn derive_das_structure(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_structure(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_structure(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "DasStructure cannot be derived for generic structs",
+        ));
+    }
+    if !has_repr(&input.attrs, "C")? {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "DasStructure requires #[repr(C)] so daScript sees the same layout",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "DasStructure requires named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "DasStructure can only be derived for structs",
+            ))
+        }
+    };
+
+    let name = das_name(&input.attrs)?.unwrap_or_else(|| ident.to_string());
+
+    let mut entries = Vec::new();
+    for field in fields {
+        if das_skip(&field.attrs)? {
+            continue;
+        }
+        let field_ident = field.ident.as_ref().expect("named field");
+        let field_name = field_ident.to_string();
+        let ty = &field.ty;
+        entries.push(quote! {
+            ::dastrap::interop::DasField {
+                name: #field_name,
+                offset: ::core::mem::offset_of!(#ident, #field_ident),
+                mangled: <#ty as ::dastrap::interop::DasFieldType>::MANGLED,
+            }
+        });
+    }
+
+    Ok(quote! {
+        unsafe impl ::dastrap::interop::DasStructure for #ident {
+            const NAME: &'static str = #name;
+
+            fn fields() -> ::std::vec::Vec<::dastrap::interop::DasField> {
+                ::std::vec![#(#entries),*]
+            }
+        }
+    })
+}
+
+/// Whether `#[repr(...)]` lists `kind`
+fn has_repr(attrs: &[Attribute], kind: &str) -> syn::Result<bool> {
+    let mut found = false;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(kind) {
+                found = true;
+            } else if meta.input.peek(syn::token::Paren) {
+                // align(N), packed(N)
+                let _content;
+                syn::parenthesized!(_content in meta.input);
+            }
+            Ok(())
+        })?;
+    }
+    Ok(found)
+}
+
+/// Bind a fieldless enum as a daScript enumeration.
+///
+/// - `#[das(name = "...")]` on the enum renames it on the script side
+///
+/// Host functions exchange it as the enumeration, take `Option<Self>` to reject unknown values.
+#[proc_macro_derive(DasEnumeration, attributes(das))]
+pub fn derive_das_enumeration(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_enumeration(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_enumeration(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "DasEnumeration cannot be derived for generic enums",
+        ));
+    }
+
+    let variants = match &input.data {
+        Data::Enum(data) => &data.variants,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "DasEnumeration can only be derived for enums",
+            ))
+        }
+    };
+    for variant in variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                "DasEnumeration requires fieldless variants",
+            ));
+        }
+    }
+
+    let name = das_name(&input.attrs)?.unwrap_or_else(|| ident.to_string());
+    let names = variants.iter().map(|v| v.ident.to_string());
+    let idents: Vec<_> = variants.iter().map(|v| &v.ident).collect();
+    let overflows = idents
+        .iter()
+        .map(|v| format!("{}::{} does not fit in daScript's int", ident, v));
+
+    Ok(quote! {
+        // wider reprs would be cut down by the `as i32` casts below
+        const _: () = {
+            #(
+                ::core::assert!(
+                    #ident::#idents as i128 >= i32::MIN as i128
+                        && #ident::#idents as i128 <= i32::MAX as i128,
+                    #overflows
+                );
+            )*
+        };
+
+        unsafe impl ::dastrap::interop::DasEnumeration for #ident {
+            const NAME: &'static str = #name;
+            const MANGLED: &'static str = ::core::concat!("E<", #name, ">");
+
+            fn values() -> ::std::vec::Vec<(&'static str, i32)> {
+                ::std::vec![#((#names, #ident::#idents as i32)),*]
+            }
+
+            fn to_int(self) -> i32 {
+                self as i32
+            }
+
+            fn from_int(value: i32) -> ::core::option::Option<Self> {
+                #(
+                    if value == #ident::#idents as i32 {
+                        return ::core::option::Option::Some(#ident::#idents);
+                    }
+                )*
+                ::core::option::Option::None
+            }
+        }
+
+        impl ::dastrap::interop::IntoDasResult for #ident {
+            const MANGLED: &'static str = <Self as ::dastrap::interop::DasEnumeration>::MANGLED;
+
+            unsafe fn into_result(self, result: *mut ::dastrap::bindings::das::vec4f_unaligned) {
+                ::dastrap::interop::IntoDasResult::into_result(
+                    <Self as ::dastrap::interop::DasEnumeration>::to_int(self),
+                    result,
+                )
+            }
+        }
+
+        impl ::core::convert::From<#ident> for ::dastrap::interop::VMArg<'_> {
+            fn from(value: #ident) -> Self {
+                ::dastrap::interop::VMArg::Int(
+                    <#ident as ::dastrap::interop::DasEnumeration>::to_int(value),
+                )
+            }
+        }
+    })
+}
+
+/// `#[das(name = "...")]`
+fn das_name(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut name = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("das")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported das attribute"))
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+/// `#[das(skip)]`
+fn das_skip(attrs: &[Attribute]) -> syn::Result<bool> {
+    let mut skip = false;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("das")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported das attribute"))
+            }
+        })?;
+    }
+    Ok(skip)
+}
@@ -1,10 +1,50 @@
-use dastrap::interop::VMEngine;
+use dastrap::interop::{
+    CompilePolicies, DasEnumeration, DasStructure, LogOutput, VMArg, VMEngine, VMError,
+    VMHostOptions, VMType, VMValue,
+};
+use std::time::Duration;
+
+#[repr(C)]
+#[derive(Debug, DasStructure)]
+struct Counter {
+    value: i32,
+    step: i32,
+}
+
+#[derive(Debug, Clone, Copy, DasEnumeration)]
+enum Mood {
+    Calm,
+    Excited = 10,
+}
 
 fn main() {
     femme::with_level(log::LevelFilter::Debug);
 
     // let program = VMProgram::new("examples/example.das").expect("Example failed: Failed to compile example.das");
-    let mut engine = VMEngine::new().expect("VMEngine failed to initialize");
+    let mut engine = VMEngine::builder()
+        .output(LogOutput)
+        .build()
+        .expect("VMEngine failed to initialize");
+
+    engine
+        .module("host")
+        .expect("Failed to create host module")
+        .function("host_mul", |a: i32, b: i32| a * b)
+        .expect("Failed to bind host_mul")
+        .function("host_log", |message: String| {
+            log::info!("script: {}", message)
+        })
+        .expect("Failed to bind host_log")
+        .structure::<Counter>()
+        .expect("Failed to bind Counter")
+        .enumeration::<Mood>()
+        .expect("Failed to bind Mood")
+        .function("host_mood", |mood: Option<Mood>| {
+            log::info!("script mood: {:?}", mood)
+        })
+        .expect("Failed to bind host_mood")
+        .function("host_fail", || -> () { panic!("host refused") })
+        .expect("Failed to bind host_fail");
 
     let program = engine
         .load("examples/example.das")
@@ -16,17 +56,152 @@ fn main() {
         .host()
         .expect("g");
 
-    _program_b.eval_function("examplelib_test");
+    _program_b
+        .eval_function("examplelib_test")
+        .expect("examplelib_test failed");
+
+    // the panic comes back as a script exception instead of tearing the process down
+    let failed = _program_b.eval_function("fail");
+    assert!(
+        matches!(failed, Err(VMError::Exception { .. })),
+        "{:?}",
+        failed
+    );
+
+    let mut counter = Counter { value: 1, step: 2 };
+    _program_b
+        .eval_function_args("bump", &[VMArg::Ptr((&mut counter as *mut Counter).cast())])
+        .expect("bump failed");
+    log::info!("bumped: {:?}", counter);
 
     let context = program
-        .host()
+        .host_with(
+            VMHostOptions::new()
+                .min_stack(64 * 1024)
+                .heap_limit(16 * 1024 * 1024),
+        )
         .expect("Example failed: Failed to host program.");
 
-    context.eval_function("_not_exist");
+    if let Err(e) = context.eval_function("_not_exist") {
+        log::error!("{}", e);
+    }
+
+    context.eval_function("test").expect("test failed");
+
+    let printed = context
+        .eval_function_captured("test", &[])
+        .expect("captured test failed");
+    log::info!("test printed {:?}", printed);
+
+    context.eval_function("main").expect("main failed");
+
+    context
+        .eval_function_args("greet", &[VMArg::String("dastrap"), VMArg::Int(2)])
+        .expect("greet failed");
+
+    let misfit = context.eval_function_args("greet", &[VMArg::Int(2)]);
+    assert!(
+        matches!(misfit, Err(VMError::Signature { .. })),
+        "{:?}",
+        misfit
+    );
+
+    let sum = context
+        .call_function("add", &[VMArg::Int(3), VMArg::Int(4)], VMType::Int)
+        .expect("add failed");
+    assert_eq!(sum, VMValue::Int(7));
+    log::info!("add(3, 4) = {:?}", sum);
+
+    context
+        .set_time_budget(Some(Duration::from_millis(100)))
+        .expect("Failed to set budget");
+    // 128MB of ints does not fit the 16MB heap it was hosted with
+    let hogged = context.eval_function("hog");
+    assert!(
+        matches!(hogged, Err(VMError::Exception { .. })),
+        "{:?}",
+        hogged
+    );
+    log::info!("hog stopped: {}", hogged.unwrap_err());
+
+    let spun = context.eval_function("spin");
+    assert!(matches!(spun, Err(VMError::Timeout { .. })), "{:?}", spun);
+    log::info!("spin stopped: {}", spun.unwrap_err());
+    context
+        .set_time_budget(None)
+        .expect("Failed to lift budget");
+    log::info!("memory: {:?}", context.memory_stats());
+
+    let add = context.function("add").expect("add not found");
+    for i in 0..3 {
+        let doubled = add.call(&[VMArg::Int(i), VMArg::Int(i)], VMType::Int);
+        log::info!("add({}, {}) = {:?}", i, i, doubled);
+    }
+
+    let pool = program.pool(2).expect("Failed to pool contexts");
+    for i in 0..4 {
+        let lease = pool.lease().expect("Failed to lease context");
+        let sum = lease.call_function("add", &[VMArg::Int(i), VMArg::Int(1)], VMType::Int);
+        log::info!("pooled add({}, 1) = {:?}, {} idle", i, sum, pool.idle());
+    }
+
+    // every worker leases its own context, the program is shared
+    std::thread::scope(|scope| {
+        for i in 0..2 {
+            let pool = &pool;
+            scope.spawn(move || {
+                let lease = pool.lease().expect("Failed to lease context");
+                let sum = lease.call_function("add", &[VMArg::Int(i), VMArg::Int(i)], VMType::Int);
+                log::info!("worker {}: add = {:?}", i, sum);
+            });
+        }
+    });
+
+    let inline = engine
+        .load_sources(
+            "inline/main.das",
+            &[
+                (
+                    "inline/main.das",
+                    "require helper\n\n[export]\ndef main\n    print(\"{greeting()}\\n\")\n",
+                ),
+                (
+                    "inline/helper.das",
+                    "module helper\n\ndef public greeting : string\n    return \"from memory\"\n",
+                ),
+            ],
+        )
+        .expect("Failed to load in-memory program")
+        .host()
+        .expect("Failed to host in-memory program");
+
+    inline.eval_function("main").expect("inline main failed");
+
+    // mutable globals are refused under the sandbox policies
+    engine
+        .introduce(
+            "inline/untrusted.das",
+            "var counter = 0\n\n[export]\ndef main\n    counter++\n",
+        )
+        .expect("Failed to introduce untrusted script");
+    let untrusted = engine.load_with("inline/untrusted.das", CompilePolicies::sandbox());
+    assert!(
+        matches!(untrusted, Err(VMError::Compile { .. })),
+        "untrusted script compiled"
+    );
+    log::info!("untrusted script rejected: {}", untrusted.err().unwrap());
 
-    context.eval_function("test");
+    for (path, result) in engine.reload_changed() {
+        log::info!("reloaded {}: {:?}", path, result);
+    }
 
-    context.eval_function("main");
+    for prog in engine.programs() {
+        log::info!("loaded: {}", prog.path());
+    }
+    engine
+        .unload("inline/main.das")
+        .expect("inline program not loaded");
+    log::info!("inline context alive after unload: {}", inline.is_alive());
 
     // dastrap::interop::engine_shutdown();
 }
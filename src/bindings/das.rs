@@ -31,10 +31,12 @@ pub use super::c::{
     das_get_root,
     das_initialize,
     das_interop_function,
+    das_interop_function_unaligned,
     das_module,
     das_module_bind_alias,
     das_module_bind_enumeration,
     das_module_bind_interop_function,
+    das_module_bind_interop_function_unaligned,
     das_module_bind_structure,
     das_module_create,
     das_module_group,
@@ -67,8 +69,14 @@ pub use super::c::{
 
     // dascript data struct
     vec4f_unaligned,
+    // side effects of bound functions
+    SIDEEFFECTS_accessExternal,
+    SIDEEFFECTS_modifyExternal,
+    SIDEEFFECTS_none,
+    SIDEEFFECTS_worstDefault,
 };
 
+#[repr(transparent)]
 pub struct V4FloatUnlined(vec4f_unaligned);
 
 impl<'a> V4FloatUnlined {
@@ -0,0 +1,126 @@
+//! Engine configuration
+
+use super::{
+    output::{OutputHandle, SharedOutput},
+    runtime, CompilePolicies, StdOutput, VMCore, VMEngine, VMError, VMFileAccess, VMFiles,
+    VMModuleBuilder, VMOutput, VMResult, VMRuntime, VMState,
+};
+use crate::bindings::das::das_modulegroup_make;
+use log::debug;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+type ModuleBind = Box<dyn FnOnce(&mut VMModuleBuilder<'_>) -> VMResult<()>>;
+
+/// Options an engine is created with, see `VMEngine::builder`
+#[derive(Default)]
+pub struct VMEngineBuilder {
+    access: VMFileAccess,
+    output: Option<SharedOutput>,
+    stack_size: Option<usize>,
+    modules: Vec<(String, ModuleBind)>,
+    policies: CompilePolicies,
+}
+
+impl VMEngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where scripts are read from, `VMFileAccess::Default` if unset
+    pub fn file_access(mut self, access: VMFileAccess) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Resolve modules through a `.das_project` file
+    pub fn project(self, project: &str) -> Self {
+        self.file_access(VMFileAccess::Project(project.to_string()))
+    }
+
+    /// Sink for compiler messages and script output, `StdOutput` if unset
+    pub fn output(mut self, output: impl VMOutput + 'static) -> Self {
+        self.output = Some(Arc::new(output));
+        self
+    }
+
+    /// Stack size in bytes for every hosted context, instead of what each program asks for.
+    /// `VMHostOptions` can still override it per context.
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Policies every program is compiled under, see `VMEngine::load_with` to pick per program
+    pub fn policies(mut self, policies: CompilePolicies) -> Self {
+        self.policies = policies;
+        self
+    }
+
+    /// Register a host module, `bind` adds its functions and types.
+    /// Modules are bound in the order they were added, once the engine exists.
+    pub fn module(
+        mut self,
+        name: &str,
+        bind: impl FnOnce(&mut VMModuleBuilder<'_>) -> VMResult<()> + 'static,
+    ) -> Self {
+        self.modules.push((name.to_string(), Box::new(bind)));
+        self
+    }
+
+    pub fn build(self) -> VMResult<VMEngine> {
+        let runtime = VMRuntime::acquire();
+
+        let mut engine = unsafe {
+            let _env = runtime::environment();
+            let files = VMFiles::make(&self.access, HashMap::new(), std::iter::empty())?;
+
+            debug!("VM: Creating module group");
+            let das_libs = das_modulegroup_make();
+            if das_libs.is_null() {
+                return Err(VMError::Init("module group"));
+            }
+            // from here on the core releases both on failure
+            let core = Arc::new(VMCore {
+                access: self.access,
+                das_libs,
+                files: Mutex::new(Arc::new(files)),
+                _runtime: runtime,
+            });
+
+            debug!("VM: Creating text output");
+            let output = self.output.unwrap_or_else(|| Arc::new(StdOutput));
+            let tout_output = OutputHandle::new(output.clone());
+            let das_tout = tout_output.make_writer();
+            if das_tout.is_null() {
+                return Err(VMError::Init("text output"));
+            }
+
+            let state = VMState {
+                hanged: false,
+                tracked: Vec::new(),
+                output,
+                stack_size: self.stack_size,
+            };
+
+            VMEngine {
+                core,
+                das_tout,
+                tout_output,
+                state: Arc::new(RwLock::new(state)),
+                sys_progs: HashMap::new(),
+                modules: HashMap::new(),
+                enumerations: HashSet::new(),
+                policies: self.policies,
+            }
+        };
+
+        for (name, bind) in self.modules {
+            bind(&mut engine.module(&name)?)?;
+        }
+        Ok(engine)
+    }
+}
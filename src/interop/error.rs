@@ -0,0 +1,230 @@
+//! Errors surfaced by the interop layer
+
+use crate::bindings::das::{
+    das_error_report, das_program, das_program_err_count, das_program_get_error,
+};
+use log::debug;
+use std::{ffi::CStr, fmt, time::Duration};
+
+/// Size of the buffer a single error report is rendered into
+const REPORT_BUFFER: usize = 4096;
+
+/// A compiler or simulation error reported by daScript
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The full report text as daScript renders it
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl Diagnostic {
+    /// Parse a report, daScript leads located errors with `file:line:column:`
+    pub fn parse(message: String) -> Self {
+        let location = message.lines().next().and_then(|head| {
+            let mut parts = head.trim_end().strip_suffix(':')?.rsplitn(3, ':');
+            let column = parts.next()?.trim().parse().ok()?;
+            let line = parts.next()?.trim().parse().ok()?;
+            let file = parts.next().filter(|f| !f.is_empty())?;
+            Some((file.to_string(), line, column))
+        });
+
+        match location {
+            Some((file, line, column)) => Diagnostic {
+                message,
+                file: Some(file),
+                line: Some(line),
+                column: Some(column),
+            },
+            None => Diagnostic {
+                message,
+                file: None,
+                line: None,
+                column: None,
+            },
+        }
+    }
+
+    /// Collect every error attached to a program
+    pub(crate) unsafe fn collect(program: *mut das_program) -> Vec<Diagnostic> {
+        if program.is_null() {
+            return Vec::new();
+        }
+
+        let err_count = das_program_err_count(program);
+        let mut diagnostics = Vec::with_capacity(err_count.max(0) as usize);
+        let mut buffer = vec![0u8; REPORT_BUFFER];
+        for i in 0..err_count {
+            let error = das_program_get_error(program, i);
+            if error.is_null() {
+                continue;
+            }
+
+            buffer.fill(0);
+            das_error_report(error, buffer.as_mut_ptr().cast(), REPORT_BUFFER as i32 - 1);
+            let text = CStr::from_bytes_until_nul(&buffer)
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            debug!("VM: Diagnostic {}: {}", i, text.trim_end());
+            diagnostics.push(Diagnostic::parse(text));
+        }
+        diagnostics
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message.trim_end())
+    }
+}
+
+/// Everything that can go wrong between the host and daScript
+#[derive(Debug, Clone, PartialEq)]
+pub enum VMError {
+    /// A runtime resource (file access, text output, module group, context) could not be created
+    Init(&'static str),
+    /// A context stack size, in bytes, daScript cannot use
+    StackSize(usize),
+    /// The script path cannot be handed to daScript
+    InvalidPath(String),
+    /// A name or string argument contains an interior NUL
+    InvalidString(String),
+    /// The program failed to compile
+    Compile {
+        path: String,
+        diagnostics: Vec<Diagnostic>,
+    },
+    /// The program failed to simulate into a context
+    Simulation(Vec<Diagnostic>),
+    /// No exported function with this name exists in the context
+    FunctionNotFound(String),
+    /// The arguments, or the expected result, do not match what the function declares
+    Signature { function: String, reason: String },
+    /// The script raised an exception while evaluating a function
+    Exception { function: String, message: String },
+    /// The context was released by engine teardown or by unloading its program,
+    /// see `VMHangedLock::is_alive`
+    Destroyed,
+    /// No program is loaded under this path
+    ProgramNotFound(String),
+    /// A program is already loaded under this path, see `VMEngine::replace`
+    DuplicateProgram(String),
+    /// A module with this name is already registered on the engine
+    DuplicateModule(String),
+    /// A host function captures state and cannot be bound
+    CapturingFunction(String),
+    /// A host function takes or returns an enumeration no host module bound yet
+    UnboundEnumeration {
+        function: String,
+        enumeration: String,
+    },
+    /// The call ran longer than the context's time budget
+    Timeout { function: String, budget: Duration },
+    /// The call was stopped through a `VMCancel` handle
+    Cancelled(String),
+}
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VMError::Init(what) => write!(f, "failed to create {}", what),
+            VMError::StackSize(bytes) => write!(f, "stack size of {} bytes is out of range", bytes),
+            VMError::InvalidPath(path) => write!(f, "invalid script path '{}'", path),
+            VMError::InvalidString(s) => write!(f, "string contains a NUL byte: {:?}", s),
+            VMError::Compile { path, diagnostics } => {
+                write!(f, "failed to compile '{}'", path)?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n{}", diagnostic)?;
+                }
+                Ok(())
+            }
+            VMError::Simulation(diagnostics) => {
+                write!(f, "program simulation failed")?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n{}", diagnostic)?;
+                }
+                Ok(())
+            }
+            VMError::FunctionNotFound(name) => write!(f, "function '{}' not found", name),
+            VMError::Signature { function, reason } => {
+                write!(f, "cannot call '{}', it {}", function, reason)
+            }
+            VMError::Exception { function, message } => {
+                write!(f, "exception while evaluating '{}': {}", function, message)
+            }
+            VMError::Destroyed => write!(f, "context has been destroyed"),
+            VMError::ProgramNotFound(path) => write!(f, "no program loaded from '{}'", path),
+            VMError::DuplicateProgram(path) => {
+                write!(f, "program '{}' is already loaded", path)
+            }
+            VMError::DuplicateModule(name) => write!(f, "module '{}' is already registered", name),
+            VMError::CapturingFunction(name) => write!(
+                f,
+                "host function '{}' captures state, only fn items and non-capturing closures can be bound",
+                name
+            ),
+            VMError::UnboundEnumeration {
+                function,
+                enumeration,
+            } => write!(
+                f,
+                "host function '{}' uses enumeration '{}', bind it first",
+                function, enumeration
+            ),
+            VMError::Timeout { function, budget } => {
+                write!(f, "function '{}' ran out of its {:?} budget", function, budget)
+            }
+            VMError::Cancelled(function) => write!(f, "function '{}' was cancelled", function),
+        }
+    }
+}
+
+impl std::error::Error for VMError {}
+
+pub type VMResult<T> = Result<T, VMError>;
+
+#[cfg(test)]
+mod tests {
+    use super::Diagnostic;
+
+    fn location(message: &str) -> (Option<String>, Option<u32>, Option<u32>) {
+        let diagnostic = Diagnostic::parse(message.to_string());
+        (diagnostic.file, diagnostic.line, diagnostic.column)
+    }
+
+    #[test]
+    fn parses_leading_location() {
+        assert_eq!(
+            location("scripts/main.das:12:5:\nerror[30304]: can't locate variable 'x'\n"),
+            (Some("scripts/main.das".to_string()), Some(12), Some(5))
+        );
+    }
+
+    #[test]
+    fn keeps_windows_drive_in_file() {
+        assert_eq!(
+            location("C:\\scripts\\main.das:3:14:\nerror[20605]: unexpected token\n"),
+            (Some("C:\\scripts\\main.das".to_string()), Some(3), Some(14))
+        );
+    }
+
+    #[test]
+    fn leaves_unlocated_reports_alone() {
+        let message = "error[40102]: function not found\n";
+        let diagnostic = Diagnostic::parse(message.to_string());
+        assert_eq!(diagnostic.message, message);
+        assert_eq!(
+            (diagnostic.file, diagnostic.line, diagnostic.column),
+            (None, None, None)
+        );
+    }
+
+    #[test]
+    fn rejects_partial_locations() {
+        assert_eq!(location("main.das:12:\n"), (None, None, None));
+        assert_eq!(location(":12:5:\n"), (None, None, None));
+        assert_eq!(location("main.das:x:5:\n"), (None, None, None));
+        assert_eq!(location(""), (None, None, None));
+    }
+}
@@ -1,16 +1,240 @@
 #include "ext.h"
 
+#include <atomic>
+#include <filesystem>
+#include <set>
+
 // static void iTestTheApiFrNotYetUseful() {
 //     das::Context * context;
 //     das::SimFunction * fn = context->getFunction(1);
 //     context->evalWithCatch(fn, nullptr, nullptr);
 // }
 
-static bool dasx_verif_fn(das::SimFunction * fun, char * name) {
+bool dasx_verif_fn(das_function * fn, char * name) {
+    auto fun = (das::SimFunction *) fn;
     if (fun != nullptr && name != nullptr) {
-        if (strcmp(fun->name, name)) {
+        if (strcmp(fun->name, name) == 0) {
             return true;
         }
     }
     return false;
-}
\ No newline at end of file
+}
+
+// what a call slot of this type is read as, numbered like VMType on the Rust side
+static int dasx_slot_kind(const das::TypeInfo * info, bool argument) {
+    if (info->flags & das::TypeInfo::flag_ref) return 5;
+    // structures and the like are passed by reference, but returned through a copy
+    if (info->flags & das::TypeInfo::flag_refType) return argument ? 5 : -1;
+    switch (info->type) {
+        case das::Type::tVoid: return 0;
+        case das::Type::tInt:
+        case das::Type::tEnumeration: return 1;
+        case das::Type::tFloat: return 2;
+        case das::Type::tDouble: return 3;
+        case das::Type::tString: return 4;
+        case das::Type::tPointer: return 5;
+        default: return -1;
+    }
+}
+
+int dasx_function_signature(das_function * fn, int * result, int * args, int max_args) {
+    auto info = ((das::SimFunction *) fn)->debugInfo;
+    if (info == nullptr) return -1;
+    *result = dasx_slot_kind(info->result, false);
+    for (uint32_t i = 0; i < info->count && i < (uint32_t) max_args; i++) {
+        args[i] = dasx_slot_kind(info->fields[i], true);
+    }
+    return (int) info->count;
+}
+
+// forwards whatever was written since the last flush, like das::TextPrinter does to stdout
+class CallbackWriter : public das::TextWriter {
+public:
+    CallbackWriter(void * user, dasx_output_fn fn) : user(user), fn(fn) {}
+    virtual void output() override {
+        int newPos = tellp();
+        if (newPos != pos) {
+            fn(user, data.data() + pos, newPos - pos, 1);
+            pos = newPos;
+        }
+    }
+protected:
+    void * user;
+    dasx_output_fn fn;
+    int pos = 0;
+};
+
+// script print and error go through to_out / to_err, which default to stdout
+class CallbackContext : public das::Context {
+public:
+    CallbackContext(int stackSize, void * user, dasx_output_fn fn)
+        : das::Context(stackSize), user(user), fn(fn) {}
+    virtual void to_out(const das::LineInfo *, const char * message) override {
+        if (message) fn(user, message, int(strlen(message)), 0);
+    }
+    virtual void to_err(const das::LineInfo *, const char * message) override {
+        if (message) fn(user, message, int(strlen(message)), 1);
+    }
+    std::atomic<int> interrupt{0};
+    // whether a call from the host is in flight, stops are dropped otherwise
+    std::atomic<bool> running{false};
+protected:
+    void * user;
+    dasx_output_fn fn;
+};
+
+das_text_writer * dasx_text_make_callback(void * user, dasx_output_fn fn) {
+    return (das_text_writer *) new CallbackWriter(user, fn);
+}
+
+das_context * dasx_context_make_with_output(int stackSize, void * user, dasx_output_fn fn) {
+    return (das_context *) new CallbackContext(stackSize, user, fn);
+}
+
+// stopForThrow unwinds loops and blocks like a throw would, without an exception message.
+// daScript has no hook to stop a context from another thread: stopFlags is a plain int the
+// interpreter reads and writes too, so this write races with it. The running code may see
+// it late or have it wiped by its own update, the host re-raises until the call returns.
+void dasx_context_interrupt(das_context * ctx, int reason) {
+    auto context = (CallbackContext *) ctx;
+    if (!context->running.load()) return;
+    context->interrupt.store(reason);
+    context->stopFlags |= das::EvalFlags::stopForThrow;
+}
+
+void dasx_context_throw(das_context * ctx, const char * message) {
+    auto context = (das::Context *) ctx;
+    // the caller's buffer is gone by the time anyone reads the exception
+    auto copy = context->stringHeap->allocateString(message, uint32_t(strlen(message)));
+    context->throw_error(copy ? copy : "host function panicked");
+}
+
+void dasx_context_set_running(das_context * ctx, int running) {
+    ((CallbackContext *) ctx)->running.store(running != 0);
+}
+
+int dasx_context_take_interrupt(das_context * ctx) {
+    auto context = (CallbackContext *) ctx;
+    int reason = context->interrupt.exchange(0);
+    if (reason) context->stopFlags = 0;
+    return reason;
+}
+
+void dasx_context_set_heap_limit(das_context * ctx, uint64_t heap, uint64_t strings) {
+    auto context = (das::Context *) ctx;
+    if (heap) context->heap->setLimit(heap);
+    if (strings) context->stringHeap->setLimit(strings);
+}
+
+void dasx_context_memory(das_context * ctx, uint64_t * heap, uint64_t * strings) {
+    auto context = (das::Context *) ctx;
+    *heap = context->heap->bytesAllocated();
+    *strings = context->stringHeap->bytesAllocated();
+}
+
+int dasx_context_reset(das_context * ctx) {
+    auto context = (das::Context *) ctx;
+    context->restart();
+    context->restartHeaps();
+    return context->runInitScript() ? 1 : 0;
+}
+
+void * dasx_env_current() {
+    return das::daScriptEnvironment::bound;
+}
+
+void dasx_env_bind(void * env) {
+    // only bound, owned stays with the initializing thread so nothing else frees it
+    das::daScriptEnvironment::bound = (das::daScriptEnvironment *) env;
+}
+
+void dasx_env_adopt(void * env) {
+    das::daScriptEnvironment::bound = (das::daScriptEnvironment *) env;
+    das::daScriptEnvironment::owned = (das::daScriptEnvironment *) env;
+}
+
+void dasx_env_clear() {
+    das::daScriptEnvironment::bound = nullptr;
+    das::daScriptEnvironment::owned = nullptr;
+}
+
+das_program * dasx_program_compile(char * file, das_file_access * access, das_text_writer * tout,
+                                   das_module_group * libs, const dasx_policies * p) {
+    das::CodeOfPolicies policies;
+    policies.no_unsafe = p->no_unsafe;
+    policies.no_global_variables = p->no_global_variables;
+    policies.no_global_variables_at_all = p->no_global_variables_at_all;
+    policies.no_global_heap = p->no_global_heap;
+    policies.no_init = p->no_init;
+    policies.no_aliasing = p->no_aliasing;
+    policies.no_deprecated = p->no_deprecated;
+    policies.strict_smart_pointers = p->strict_smart_pointers;
+    policies.strict_unsafe_delete = p->strict_unsafe_delete;
+    policies.unsafe_table_lookup = p->unsafe_table_lookup;
+    auto program = das::compileDaScript(file, das::FileAccessPtr((das::FileAccess *) access),
+                                        *(das::TextWriter *) tout, *(das::ModuleGroup *) libs, policies);
+    if (!program) return nullptr;
+    // same ownership as das_program_compile, released with das_program_release
+    program->addRef();
+    return (das_program *) program.get();
+}
+
+// introduced files are found before getNewFileInfo is asked, the base one finds nothing
+class MemoryFileAccess : public das::FileAccess {};
+
+// refuses to read files resolving outside of root, symlinks and .. included,
+// and native modules that were not allowed, fio and friends included
+class SandboxFileAccess : public das::FsFileAccess {
+public:
+    SandboxFileAccess(const char * root)
+        : root(canonical(root)), daslib(canonical((das::getDasRoot() + "/daslib").c_str())) {}
+    virtual das::FileInfo * getNewFileInfo(const das::string & fileName) override {
+        auto path = canonical(fileName.c_str());
+        if (!inside(path, root) && !inside(path, daslib)) return nullptr;
+        return das::FsFileAccess::getNewFileInfo(fileName);
+    }
+    virtual bool isModuleAllowed(const das::string & moduleName, const das::string & fileName) const override {
+        if (allowed.count(moduleName)) return true;
+        auto native = das::Module::require(moduleName);
+        if (native && native->builtIn) return false;
+        // script modules, daslib ones still go through this for what they require
+        auto path = canonical(fileName.c_str());
+        return inside(path, root) || inside(path, daslib);
+    }
+    void allow(const char * moduleName) {
+        allowed.insert(moduleName);
+    }
+protected:
+    static std::filesystem::path canonical(const char * path) {
+        std::error_code ec;
+        auto result = std::filesystem::weakly_canonical(path, ec);
+        return ec ? std::filesystem::path() : result;
+    }
+    static bool inside(const std::filesystem::path & path, const std::filesystem::path & dir) {
+        if (path.empty() || dir.empty()) return false;
+        auto rel = path.lexically_relative(dir);
+        return !rel.empty() && *rel.begin() != "..";
+    }
+    std::filesystem::path root;
+    // only daslib itself, the das root is often a whole install prefix
+    std::filesystem::path daslib;
+    // native modules scripts may require, host modules are added as they are bound
+    std::set<das::string> allowed = {"$", "math", "strings"};
+};
+
+// same ownership as das_fileaccess_make_default, released with das_fileaccess_release
+das_file_access * dasx_fileaccess_make_memory() {
+    auto access = new MemoryFileAccess();
+    access->addRef();
+    return (das_file_access *) access;
+}
+
+das_file_access * dasx_fileaccess_make_sandboxed(const char * root) {
+    auto access = new SandboxFileAccess(root);
+    access->addRef();
+    return (das_file_access *) access;
+}
+
+void dasx_fileaccess_sandbox_allow(das_file_access * access, const char * module) {
+    ((SandboxFileAccess *) access)->allow(module);
+}
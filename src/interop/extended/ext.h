@@ -1,2 +1,67 @@
+#pragma once
+
 #include <daScript/daScript.h>
-#include <daScript/daScriptC.h>
\ No newline at end of file
+#include <daScript/daScriptC.h>
+
+extern "C" {
+
+// text produced by a writer or by a context's print/error output
+typedef void (*dasx_output_fn)(void * user, const char * text, int length, int is_error);
+
+bool dasx_verif_fn(das_function * fun, char * name);
+// slot kinds of the result and of up to max_args arguments, numbered like VMType,
+// -1 for types no VMArg or VMType stands for. Returns the argument count, -1 without debug info
+int dasx_function_signature(das_function * fun, int * result, int * args, int max_args);
+
+das_text_writer * dasx_text_make_callback(void * user, dasx_output_fn fn);
+das_context * dasx_context_make_with_output(int stackSize, void * user, dasx_output_fn fn);
+// stop the call running in the context at its next check, from any thread, nothing without one
+void dasx_context_interrupt(das_context * context, int reason);
+// raise a script exception from inside a host function, does not return.
+// Exceptions are off, so this longjmps: the calling frame must hold nothing to clean up
+void dasx_context_throw(das_context * context, const char * message);
+// mark a call from the host as in flight, serialized with dasx_context_interrupt by the caller
+void dasx_context_set_running(das_context * context, int running);
+// the reason of the last stop, 0 if none, clearing it and the stop flags
+int dasx_context_take_interrupt(das_context * context);
+// 0 leaves a heap unlimited, allocating past a limit raises a script exception
+void dasx_context_set_heap_limit(das_context * context, uint64_t heap, uint64_t strings);
+void dasx_context_memory(das_context * context, uint64_t * heap, uint64_t * strings);
+// back to the state right after simulation: stack, heaps and globals, 0 if the init script failed
+int dasx_context_reset(das_context * context);
+
+// daScript keeps its environment (module registry, options) in thread locals,
+// other threads bind the initializing thread's one the way jobque's new_thread does
+void * dasx_env_current();
+void dasx_env_bind(void * env);
+// take ownership on this thread, for the das_shutdown that frees it
+void dasx_env_adopt(void * env);
+// forget whatever this thread had bound, left dangling once the runtime shut down
+void dasx_env_clear();
+
+// mirrors the CodeOfPolicies fields dastrap exposes, same order as the Rust side
+struct dasx_policies {
+    bool no_unsafe;
+    bool no_global_variables;
+    bool no_global_variables_at_all;
+    bool no_global_heap;
+    bool no_init;
+    bool no_aliasing;
+    bool no_deprecated;
+    bool strict_smart_pointers;
+    bool strict_unsafe_delete;
+    bool unsafe_table_lookup;
+};
+
+// das_program_compile with a code of policies
+das_program * dasx_program_compile(char * file, das_file_access * access, das_text_writer * tout,
+                                   das_module_group * libs, const dasx_policies * policies);
+
+// file access resolving introduced files only, nothing is read from disk
+das_file_access * dasx_fileaccess_make_memory();
+// file access reading from disk only below root, and below the das root's daslib
+das_file_access * dasx_fileaccess_make_sandboxed(const char * root);
+// let scripts of a sandboxed file access require this native module
+void dasx_fileaccess_sandbox_allow(das_file_access * access, const char * module);
+
+}
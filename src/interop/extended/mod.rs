@@ -1,5 +1,70 @@
-use crate::bindings::das::das_function;
-use std::ffi::c_char;
+use crate::bindings::das::{
+    das_context, das_file_access, das_function, das_module_group, das_program, das_text_writer,
+};
+use std::ffi::{c_char, c_int, c_void};
+
+#[allow(non_camel_case_types)]
+pub(crate) type dasx_output_fn = Option<
+    unsafe extern "C" fn(user: *mut c_void, text: *const c_char, length: c_int, is_error: c_int),
+>;
+
+/// Mirrors `dasx_policies` in ext.h
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct dasx_policies {
+    pub(crate) no_unsafe: bool,
+    pub(crate) no_global_variables: bool,
+    pub(crate) no_global_variables_at_all: bool,
+    pub(crate) no_global_heap: bool,
+    pub(crate) no_init: bool,
+    pub(crate) no_aliasing: bool,
+    pub(crate) no_deprecated: bool,
+    pub(crate) strict_smart_pointers: bool,
+    pub(crate) strict_unsafe_delete: bool,
+    pub(crate) unsafe_table_lookup: bool,
+}
+
+// dasx_verif_fn is kept for debugging
+#[allow(dead_code)]
 extern "C" {
     pub(crate) fn dasx_verif_fn(fun: *mut das_function, name: *mut c_char) -> bool;
+    pub(crate) fn dasx_function_signature(
+        fun: *mut das_function,
+        result: *mut c_int,
+        args: *mut c_int,
+        max_args: c_int,
+    ) -> c_int;
+    pub(crate) fn dasx_text_make_callback(
+        user: *mut c_void,
+        fun: dasx_output_fn,
+    ) -> *mut das_text_writer;
+    pub(crate) fn dasx_context_make_with_output(
+        stack_size: c_int,
+        user: *mut c_void,
+        fun: dasx_output_fn,
+    ) -> *mut das_context;
+    pub(crate) fn dasx_context_interrupt(context: *mut das_context, reason: c_int);
+    pub(crate) fn dasx_context_set_running(context: *mut das_context, running: c_int);
+    pub(crate) fn dasx_context_throw(context: *mut das_context, message: *const c_char) -> !;
+    pub(crate) fn dasx_context_take_interrupt(context: *mut das_context) -> c_int;
+    pub(crate) fn dasx_context_set_heap_limit(context: *mut das_context, heap: u64, strings: u64);
+    pub(crate) fn dasx_context_memory(context: *mut das_context, heap: *mut u64, strings: *mut u64);
+    pub(crate) fn dasx_context_reset(context: *mut das_context) -> c_int;
+    pub(crate) fn dasx_env_current() -> *mut c_void;
+    pub(crate) fn dasx_env_bind(env: *mut c_void);
+    pub(crate) fn dasx_env_adopt(env: *mut c_void);
+    pub(crate) fn dasx_env_clear();
+    pub(crate) fn dasx_program_compile(
+        file: *mut c_char,
+        access: *mut das_file_access,
+        tout: *mut das_text_writer,
+        libs: *mut das_module_group,
+        policies: *const dasx_policies,
+    ) -> *mut das_program;
+    pub(crate) fn dasx_fileaccess_make_memory() -> *mut das_file_access;
+    pub(crate) fn dasx_fileaccess_make_sandboxed(root: *const c_char) -> *mut das_file_access;
+    pub(crate) fn dasx_fileaccess_sandbox_allow(
+        access: *mut das_file_access,
+        module: *const c_char,
+    );
 }
@@ -0,0 +1,66 @@
+//! Function handles resolved once and called many times
+
+use super::{VMArg, VMContext, VMHangedLock, VMResult, VMType, VMValue};
+use crate::bindings::das::{das_function, V4FloatUnlined};
+use log::debug;
+use std::cell::Cell;
+
+/// A script function looked up once, for calls made every frame.
+///
+/// Keeps its context alive. After a reload swapped the native context,
+/// the next call looks the name up again. Swaps are told apart by generation,
+/// a new native context may well reuse the old one's address.
+pub struct VMFunction {
+    ctx: VMHangedLock<VMContext>,
+    name: String,
+    /// Generation of the native context `function` was found in
+    resolved: Cell<(u64, *mut das_function)>,
+}
+
+// The cached pointer is only used under the context's lock, and checked against it first
+unsafe impl Send for VMFunction {}
+
+impl VMFunction {
+    pub(crate) fn new(ctx: VMHangedLock<VMContext>, name: &str) -> VMResult<Self> {
+        let resolved =
+            ctx.with(|vmctx| Ok((vmctx.generation, unsafe { vmctx.find_function(name)? })))?;
+
+        Ok(Self {
+            ctx,
+            name: name.to_string(),
+            resolved: Cell::new(resolved),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Evaluate with typed arguments
+    pub fn eval(&self, args: &[VMArg]) -> VMResult<()> {
+        self.eval_raw(args, VMType::Void).map(|_| ())
+    }
+
+    /// Evaluate with typed arguments, decoding the result as `ret`
+    pub fn call(&self, args: &[VMArg], ret: VMType) -> VMResult<VMValue> {
+        let mut result = self.eval_raw(args, ret)?;
+        Ok(unsafe { VMValue::decode(&mut result, ret) })
+    }
+
+    /// Whether the context behind this handle is still there
+    pub fn is_alive(&self) -> bool {
+        self.ctx.is_alive()
+    }
+
+    fn eval_raw(&self, args: &[VMArg], ret: VMType) -> VMResult<V4FloatUnlined> {
+        self.ctx.with(|vmctx| unsafe {
+            let (generation, mut function) = self.resolved.get();
+            if generation != vmctx.generation {
+                debug!("VM: Context changed, resolving '{}' again", self.name);
+                function = vmctx.find_function(&self.name)?;
+                self.resolved.set((vmctx.generation, function));
+            }
+            vmctx.eval(function, &self.name, args, ret)
+        })
+    }
+}
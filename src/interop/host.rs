@@ -0,0 +1,129 @@
+//! Per-context hosting options
+
+use super::{VMError, VMResult};
+use std::time::Duration;
+
+/// Options for `VMProgram::host_with`, `VMProgram::host` uses the defaults
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VMHostOptions {
+    stack_size: Option<usize>,
+    min_stack: Option<usize>,
+    max_stack: Option<usize>,
+    pub(crate) time_budget: Option<Duration>,
+    pub(crate) heap_limit: Option<u64>,
+    pub(crate) string_heap_limit: Option<u64>,
+}
+
+impl VMHostOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stack size in bytes, instead of the engine default or what the program asks for
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Raise the stack to at least `bytes`, whichever size was picked
+    pub fn min_stack(mut self, bytes: usize) -> Self {
+        self.min_stack = Some(bytes);
+        self
+    }
+
+    /// Cap the stack at `bytes`, whichever size was picked.
+    /// Scripts recursing past it stop with a stack overflow exception.
+    pub fn max_stack(mut self, bytes: usize) -> Self {
+        self.max_stack = Some(bytes);
+        self
+    }
+
+    /// How long each call may run, see `VMHangedLock::set_time_budget`
+    pub fn time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Bound the heap (arrays, tables, classes) to `bytes`,
+    /// allocating past it raises a script exception
+    pub fn heap_limit(mut self, bytes: u64) -> Self {
+        self.heap_limit = Some(bytes);
+        self
+    }
+
+    /// Bound the string heap to `bytes`, like `heap_limit`
+    pub fn string_heap_limit(mut self, bytes: u64) -> Self {
+        self.string_heap_limit = Some(bytes);
+        self
+    }
+
+    /// The stack size to create the context with, `engine` and `program` being the fallbacks in order
+    pub(crate) fn stack(&self, engine: Option<usize>, program: i32) -> VMResult<i32> {
+        if let (Some(min), Some(max)) = (self.min_stack, self.max_stack) {
+            if min > max {
+                return Err(VMError::StackSize(min));
+            }
+        }
+        let mut bytes = self
+            .stack_size
+            .or(engine)
+            .unwrap_or(program.max(0) as usize);
+        if let Some(min) = self.min_stack {
+            bytes = bytes.max(min);
+        }
+        if let Some(max) = self.max_stack {
+            bytes = bytes.min(max);
+        }
+        i32::try_from(bytes).map_err(|_| VMError::StackSize(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VMError, VMHostOptions};
+
+    #[test]
+    fn stack_size_beats_engine_and_program() {
+        let options = VMHostOptions::new().stack_size(64 * 1024);
+        assert_eq!(options.stack(Some(32 * 1024), 16 * 1024), Ok(64 * 1024));
+        assert_eq!(
+            VMHostOptions::new().stack(Some(32 * 1024), 16 * 1024),
+            Ok(32 * 1024)
+        );
+        assert_eq!(VMHostOptions::new().stack(None, 16 * 1024), Ok(16 * 1024));
+    }
+
+    #[test]
+    fn negative_program_stack_is_zero() {
+        assert_eq!(VMHostOptions::new().stack(None, -1), Ok(0));
+    }
+
+    #[test]
+    fn clamps_whichever_size_was_picked() {
+        let options = VMHostOptions::new()
+            .min_stack(8 * 1024)
+            .max_stack(32 * 1024);
+        assert_eq!(options.stack(None, 4 * 1024), Ok(8 * 1024));
+        assert_eq!(options.stack(Some(64 * 1024), 0), Ok(32 * 1024));
+        assert_eq!(options.stack_size(16 * 1024).stack(None, 0), Ok(16 * 1024));
+    }
+
+    #[test]
+    fn rejects_min_above_max() {
+        let options = VMHostOptions::new()
+            .min_stack(64 * 1024)
+            .max_stack(32 * 1024);
+        assert_eq!(options.stack(None, 0), Err(VMError::StackSize(64 * 1024)));
+    }
+
+    #[test]
+    fn rejects_sizes_past_i32() {
+        let bytes = i32::MAX as usize + 1;
+        let options = VMHostOptions::new().stack_size(bytes);
+        assert_eq!(options.stack(None, 0), Err(VMError::StackSize(bytes)));
+        assert_eq!(
+            options.max_stack(i32::MAX as usize).stack(None, 0),
+            Ok(i32::MAX)
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Stopping running calls: time budgets and cancellation from other threads
+//!
+//! A stop sets the context's stop flags, the same ones `break` and `return` use,
+//! so loops and blocks unwind at their next check. Host functions are not interrupted.
+
+use super::{
+    extended::{dasx_context_interrupt, dasx_context_set_running, dasx_context_take_interrupt},
+    VMContext, VMHangedLock, VMResult,
+};
+use crate::bindings::das::das_context;
+use log::debug;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    ffi::c_int,
+    ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub(crate) const CANCELLED: c_int = 1;
+pub(crate) const TIMEOUT: c_int = 2;
+
+/// How often an expired call is stopped again, the interpreter's own
+/// updates of the stop flags can wipe out a stop raised from outside
+const RAISE_AGAIN: Duration = Duration::from_millis(10);
+
+/// Shared between a context and its `VMCancel` handles, survives reloads
+pub(crate) struct Interrupt {
+    /// The native context to stop, null once it is released
+    context: Mutex<*mut das_context>,
+    budget: Mutex<Option<Duration>>,
+}
+
+// The pointer is only used under its mutex, and cleared before the context is released
+unsafe impl Send for Interrupt {}
+unsafe impl Sync for Interrupt {}
+
+impl Interrupt {
+    pub(crate) fn new(context: *mut das_context, budget: Option<Duration>) -> Self {
+        Self {
+            context: Mutex::new(context),
+            budget: Mutex::new(budget),
+        }
+    }
+
+    /// Forget `context`, unless a reload already pointed this at its successor
+    pub(crate) fn detach(&self, context: *mut das_context) {
+        let mut current = self.context.lock();
+        if *current == context {
+            *current = ptr::null_mut();
+        }
+    }
+
+    pub(crate) fn budget(&self) -> Option<Duration> {
+        *self.budget.lock()
+    }
+
+    /// Let stops through only while a call runs. Under the lock `raise` takes,
+    /// so once cleared no stop can land on a call that already returned.
+    pub(crate) fn set_running(&self, running: bool) {
+        let context = self.context.lock();
+        if !context.is_null() {
+            unsafe {
+                dasx_context_set_running(*context, c_int::from(running));
+            }
+        }
+    }
+
+    fn raise(&self, reason: c_int) {
+        let context = self.context.lock();
+        if !context.is_null() {
+            unsafe {
+                dasx_context_interrupt(*context, reason);
+            }
+        }
+    }
+}
+
+/// Stops whatever call is running in a context, from any thread.
+///
+/// The call fails with `VMError::Cancelled`. Without a call running it does nothing.
+#[derive(Clone)]
+pub struct VMCancel(Arc<Interrupt>);
+
+impl VMCancel {
+    pub fn cancel(&self) {
+        debug!("VM: Cancelling context");
+        self.0.raise(CANCELLED);
+    }
+}
+
+impl VMHangedLock<VMContext> {
+    /// A handle to stop calls into this context from another thread
+    pub fn cancel_handle(&self) -> VMResult<VMCancel> {
+        self.with(|vmctx| Ok(VMCancel(vmctx.interrupt.clone())))
+    }
+
+    /// Limit how long each call may run, `None` lifts the limit.
+    /// Calls running out of it fail with `VMError::Timeout`.
+    pub fn set_time_budget(&self, budget: Option<Duration>) -> VMResult<()> {
+        self.with(|vmctx| {
+            *vmctx.interrupt.budget.lock() = budget;
+            Ok(())
+        })
+    }
+}
+
+impl VMContext {
+    /// Keep the interrupt of the context this one replaces on reload, with its handles and budget
+    pub(crate) fn take_over(&mut self, old: &VMContext) {
+        self.interrupt = old.interrupt.clone();
+        *self.interrupt.context.lock() = self.context;
+    }
+
+    /// Start over with an interrupt no `VMCancel` handle points at, and `budget`
+    pub(crate) fn renew_interrupt(&mut self, budget: Option<Duration>) {
+        self.interrupt.detach(self.context);
+        self.interrupt = Arc::new(Interrupt::new(self.context, budget));
+    }
+
+    /// Clear a stop that arrived when no call was running, and return the reason of one that did
+    pub(crate) unsafe fn take_interrupt(&self) -> c_int {
+        dasx_context_take_interrupt(self.context)
+    }
+}
+
+/// Fires `TIMEOUT` on the interrupt when dropped too late
+pub(crate) struct Armed(u64);
+
+impl Drop for Armed {
+    fn drop(&mut self) {
+        watchdog()
+            .deadlines
+            .lock()
+            .retain(|(id, _, _)| *id != self.0);
+    }
+}
+
+/// Start the clock for a call
+pub(crate) fn arm(interrupt: &Arc<Interrupt>, budget: Duration) -> Armed {
+    let watchdog = watchdog();
+    let id = watchdog.next.fetch_add(1, Ordering::Relaxed);
+    let mut deadlines = watchdog.deadlines.lock();
+    deadlines.push((id, Instant::now() + budget, interrupt.clone()));
+    watchdog.wake.notify_one();
+    Armed(id)
+}
+
+/// One thread for every budget in the process, sleeping until the earliest deadline.
+/// Expired calls stay armed and are stopped again until their `Armed` drops.
+struct Watchdog {
+    deadlines: Mutex<Vec<(u64, Instant, Arc<Interrupt>)>>,
+    next: AtomicU64,
+    wake: Condvar,
+}
+
+fn watchdog() -> &'static Watchdog {
+    static WATCHDOG: OnceLock<Watchdog> = OnceLock::new();
+    WATCHDOG.get_or_init(|| {
+        thread::Builder::new()
+            .name("dastrap-watchdog".into())
+            .spawn(|| watch(watchdog()))
+            .expect("failed to spawn the budget watchdog");
+        Watchdog {
+            deadlines: Mutex::new(Vec::new()),
+            next: AtomicU64::new(0),
+            wake: Condvar::new(),
+        }
+    })
+}
+
+fn watch(watchdog: &Watchdog) {
+    let mut deadlines = watchdog.deadlines.lock();
+    loop {
+        let now = Instant::now();
+        for (_, deadline, interrupt) in deadlines.iter_mut() {
+            if *deadline <= now {
+                debug!("VM: Call ran out of its time budget");
+                interrupt.raise(TIMEOUT);
+                *deadline = now + RAISE_AGAIN;
+            }
+        }
+
+        match deadlines.iter().map(|(_, deadline, _)| *deadline).min() {
+            Some(deadline) => {
+                watchdog.wake.wait_until(&mut deadlines, deadline);
+            }
+            None => watchdog.wake.wait(&mut deadlines),
+        }
+    }
+}
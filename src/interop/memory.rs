@@ -0,0 +1,45 @@
+//! Heap usage of hosted contexts
+
+use super::{
+    extended::{dasx_context_memory, dasx_context_set_heap_limit},
+    VMContext, VMHangedLock, VMHostOptions, VMResult,
+};
+use crate::bindings::das::das_context;
+use log::debug;
+
+/// Bytes a context has allocated on its heaps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VMMemoryStats {
+    /// Arrays, tables, classes and other `new` allocations
+    pub heap: u64,
+    /// Strings built at run time
+    pub string_heap: u64,
+}
+
+impl VMHangedLock<VMContext> {
+    /// Current heap usage, for monitoring against the limits it was hosted with
+    pub fn memory_stats(&self) -> VMResult<VMMemoryStats> {
+        self.with(|vmctx| {
+            let mut stats = VMMemoryStats::default();
+            unsafe {
+                dasx_context_memory(vmctx.context, &mut stats.heap, &mut stats.string_heap);
+            }
+            Ok(stats)
+        })
+    }
+}
+
+impl VMContext {
+    /// Apply the heap limits of `options`, after simulating as it replaces the heaps.
+    /// What simulation allocated already counts toward them.
+    pub(crate) unsafe fn limit_heaps(context: *mut das_context, options: &VMHostOptions) {
+        if options.heap_limit.is_some() || options.string_heap_limit.is_some() {
+            debug!("VM: Limiting context heaps");
+            dasx_context_set_heap_limit(
+                context,
+                options.heap_limit.unwrap_or(0),
+                options.string_heap_limit.unwrap_or(0),
+            );
+        }
+    }
+}
@@ -2,22 +2,87 @@
 //!
 //! Well for convenient, please add logging for c bind calls as debug.
 //! Also whatever has to cast ptr under mut*
+//!
+//! # Threads
+//!
+//! - `VMEngine` is `Send`, not `Sync`: it compiles and registers modules from one thread at a time.
+//! - `VMProgram` is `Send + Sync`, share the `Arc` and host contexts from any thread.
+//! - `VMHangedLock<VMContext>` is `Send + Sync`, but calls into one context take its lock
+//!   and run one after another. Run in parallel with a context per thread,
+//!   e.g. a `VMPool` shared by workers, each leasing its own.
+//! - `VMFunction` is `Send`, not `Sync`.
+//!
+//! See `runtime` for how daScript's thread-local environment is handled.
 
 use crate::bindings::das::{
     das_context, das_context_eval_with_catch_unaligned, das_context_find_function,
-    das_context_get_exception, das_context_make, das_context_release, das_error_output,
-    das_file_access, das_fileaccess_make_default, das_fileaccess_release, das_initialize,
-    das_module_group, das_modulegroup_make, das_modulegroup_release, das_program,
-    das_program_compile, das_program_context_stack_size, das_program_err_count,
-    das_program_get_error, das_program_release, das_program_simulate, das_shutdown,
-    das_text_make_printer, das_text_release, das_text_writer, V4FloatUnlined,
+    das_context_get_exception, das_context_release, das_file_access, das_fileaccess_introduce_file,
+    das_fileaccess_make_default, das_fileaccess_make_project, das_fileaccess_release, das_function,
+    das_module, das_module_group, das_modulegroup_add_module, das_modulegroup_make,
+    das_modulegroup_release, das_program, das_program_context_stack_size, das_program_release,
+    das_program_simulate, das_text_release, das_text_writer, V4FloatUnlined,
+};
+use log::debug;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{CStr, CString},
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
 };
-use log::{debug, error, info};
-use parking_lot::RwLock;
-use std::{collections::HashMap, ffi::CString, sync::Arc};
+use value::{VMArgs, VMSignature};
+
+mod builder;
+mod error;
+mod extended;
+mod function;
+mod host;
+mod interrupt;
+mod memory;
+mod module;
+mod output;
+mod policies;
+mod pool;
+mod reload;
+mod runtime;
+mod structure;
+mod value;
+pub use builder::VMEngineBuilder;
+pub use dastrap_derive::{DasEnumeration, DasStructure};
+pub use error::{Diagnostic, VMError, VMResult};
+pub use function::VMFunction;
+pub use host::VMHostOptions;
+pub use interrupt::VMCancel;
+pub use memory::VMMemoryStats;
+pub use module::{FromDasArg, HostFunction, IntoDasResult, VMModuleBuilder};
+pub use output::{BufferOutput, LogOutput, StdOutput, VMOutput};
+pub use policies::CompilePolicies;
+pub use pool::{VMLease, VMPool};
+pub use runtime::{engine_initialize, engine_shutdown, VMRuntime};
+pub use structure::{DasEnumeration, DasField, DasFieldType, DasStructure};
+pub use value::{VMArg, VMType, VMValue};
 
-// mod extended;
 // use extended::dasx_verif_fn;
+use extended::{
+    dasx_fileaccess_make_memory, dasx_fileaccess_make_sandboxed, dasx_fileaccess_sandbox_allow,
+    dasx_program_compile,
+};
+use output::{ContextOutput, OutputHandle, SharedOutput};
+
+// the model in the module docs, kept honest by the compiler
+const _: () = {
+    const fn send<T: Send>() {}
+    const fn send_sync<T: Send + Sync>() {}
+    send::<VMEngine>();
+    send_sync::<VMProgram>();
+    send_sync::<VMHangedLock<VMContext>>();
+    send_sync::<VMPool>();
+    send::<VMFunction>();
+};
 
 /// `VMEngine` must flush the item before dying
 pub trait VMHang: Sized {}
@@ -25,7 +90,41 @@ pub trait VMHang: Sized {}
 /// Track for contexes, which allows graceful shutdown
 pub struct VMState {
     hanged: bool,
-    tracked: Vec<VMHangedLock<VMContext>>,
+    /// Weak, a context is released as soon as its last handle drops
+    tracked: Vec<Weak<RwLock<VMHanging<VMContext>>>>,
+    /// Sink for contexts hosted from now on
+    output: SharedOutput,
+    /// Overrides the stack size programs ask for, unless the host options do
+    stack_size: Option<usize>,
+}
+
+impl VMState {
+    fn track(&mut self, ctx: &VMHangedLock<VMContext>) {
+        // drop the entries of contexts nobody holds anymore
+        self.tracked.retain(|weak| weak.strong_count() > 0);
+        self.tracked.push(Arc::downgrade(&ctx.0));
+    }
+
+    /// Contexts that still have a handle somewhere
+    fn live(&self) -> impl Iterator<Item = VMHangedLock<VMContext>> + '_ {
+        self.tracked
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(VMHangedLock)
+    }
+
+    /// Live contexts simulated from `compiled`
+    fn hosted(&self, compiled: &Arc<VMCompiled>) -> Vec<VMHangedLock<VMContext>> {
+        self.live()
+            .filter(|ctx| {
+                ctx.0
+                    .read()
+                    .hanged
+                    .as_ref()
+                    .is_some_and(|c| Arc::ptr_eq(&c.compiled, compiled))
+            })
+            .collect()
+    }
 }
 
 /// Hanging object, which allows to drop but no
@@ -34,95 +133,443 @@ pub struct VMHanging<T: VMHang> {
     hanged: Option<Box<T>>,
 }
 
+/// Where the engine reads scripts and resolves `require` from
+#[derive(Debug, Clone, Default)]
+pub enum VMFileAccess {
+    /// Plain filesystem, modules resolve relative to the requiring file
+    #[default]
+    Default,
+    /// A `.das_project` file controlling module search paths and remapping
+    Project(String),
+    /// Only files given to `VMEngine::introduce`, nothing is read from disk.
+    /// daslib modules do not resolve either.
+    InMemory,
+    /// Plain filesystem, refusing files outside of this directory other than daslib.
+    /// Native modules other than `math`, `strings` and the engine's host modules are refused.
+    /// Introduced files are not restricted.
+    Sandboxed(String),
+}
+
+impl VMFileAccess {
+    unsafe fn make(&self) -> VMResult<*mut das_file_access> {
+        let das_fs = match self {
+            VMFileAccess::Default => das_fileaccess_make_default(),
+            VMFileAccess::Project(project) => {
+                // daScript does not report a missing project file, it just resolves nothing
+                if !Path::new(project).is_file() {
+                    return Err(VMError::InvalidPath(project.clone()));
+                }
+                let c_project = CString::new(project.as_str())
+                    .map_err(|_| VMError::InvalidPath(project.clone()))?;
+                das_fileaccess_make_project(c_project.as_ptr())
+            }
+            VMFileAccess::InMemory => dasx_fileaccess_make_memory(),
+            VMFileAccess::Sandboxed(root) => {
+                if !Path::new(root).is_dir() {
+                    return Err(VMError::InvalidPath(root.clone()));
+                }
+                let c_root =
+                    CString::new(root.as_str()).map_err(|_| VMError::InvalidPath(root.clone()))?;
+                dasx_fileaccess_make_sandboxed(c_root.as_ptr())
+            }
+        };
+        if das_fs.is_null() {
+            return Err(VMError::Init("file access"));
+        }
+        Ok(das_fs)
+    }
+
+    /// Whether the file access would read `path` from disk, what reload may look at
+    pub(crate) fn reads_disk(&self, path: &str) -> bool {
+        match self {
+            VMFileAccess::Default | VMFileAccess::Project(_) => true,
+            VMFileAccess::InMemory => false,
+            // same check as the shim, daslib is left out as it never goes stale
+            VMFileAccess::Sandboxed(root) => {
+                let (Ok(path), Ok(root)) = (fs::canonicalize(path), fs::canonicalize(root)) else {
+                    return false;
+                };
+                path.starts_with(root)
+            }
+        }
+    }
+}
+
+/// Native state shared by an engine and every program compiled with it.
+///
+/// Compiled programs point into the file access (line info) and the host modules,
+/// so this outlives the engine until the last program is gone.
+struct VMCore {
+    /// What every file access of the engine is made from
+    access: VMFileAccess,
+    das_libs: *mut das_module_group,
+    /// The file access later compiles read through
+    files: Mutex<Arc<VMFiles>>,
+    /// Dropped last, shuts the runtime down if this was the last holder
+    _runtime: VMRuntime,
+}
+
+// Only the engine touches the module group, released once on drop
+unsafe impl Send for VMCore {}
+unsafe impl Sync for VMCore {}
+
+impl Drop for VMCore {
+    fn drop(&mut self) {
+        let _env = runtime::environment();
+        unsafe {
+            debug!("VM: Releasing module group");
+            das_modulegroup_release(self.das_libs);
+        }
+    }
+}
+
+/// A file access with the in-memory files introduced into it.
+///
+/// Programs point into the file infos it read (line info), so it is replaced
+/// rather than changed, and each program holds on to the one it was compiled with.
+struct VMFiles {
+    das_fs: *mut das_file_access,
+    /// In-memory files, the file access borrows their content
+    texts: HashMap<String, Arc<CString>>,
+}
+
+impl VMFiles {
+    /// A fresh file access, nothing read from disk is cached in it yet
+    unsafe fn make<'a>(
+        access: &VMFileAccess,
+        texts: HashMap<String, Arc<CString>>,
+        modules: impl Iterator<Item = &'a String>,
+    ) -> VMResult<Self> {
+        let _env = runtime::environment();
+        debug!("VM: Creating file access: {:?}", access);
+        // released on drop from here on
+        let files = Self {
+            das_fs: access.make()?,
+            texts,
+        };
+
+        if let VMFileAccess::Sandboxed(_) = access {
+            for name in modules {
+                let c_name = CString::new(name.as_str())
+                    .map_err(|_| VMError::InvalidString(name.clone()))?;
+                dasx_fileaccess_sandbox_allow(files.das_fs, c_name.as_ptr());
+            }
+        }
+        for (name, code) in &files.texts {
+            let c_name =
+                CString::new(name.as_str()).map_err(|_| VMError::InvalidPath(name.clone()))?;
+            das_fileaccess_introduce_file(files.das_fs, c_name.as_ptr(), code.as_ptr());
+        }
+        Ok(files)
+    }
+}
+
+// Only read by compiles, under the environment lock
+unsafe impl Send for VMFiles {}
+unsafe impl Sync for VMFiles {}
+
+impl Drop for VMFiles {
+    fn drop(&mut self) {
+        let _env = runtime::environment();
+        unsafe {
+            debug!("VM: Releasing file access");
+            das_fileaccess_release(self.das_fs);
+        }
+    }
+}
+
 /// Engine, the host of dascript
 pub struct VMEngine {
-    das_fs: *mut das_file_access,
+    core: Arc<VMCore>,
     das_tout: *mut das_text_writer,
-    das_libs: *mut das_module_group,
+    /// Backs `das_tout`, dropped after it is released
+    tout_output: OutputHandle,
     state: Arc<RwLock<VMState>>,
     sys_progs: HashMap<String, Arc<VMProgram>>,
+    /// Host modules registered on the core group, every program compiles against them
+    modules: HashMap<String, *mut das_module>,
+    /// Enumerations bound by any host module, host functions can only take those
+    enumerations: HashSet<String>,
+    /// What `load` compiles with
+    policies: CompilePolicies,
 }
 
+// Everything native is reached through `&mut self` or under the environment lock
+unsafe impl Send for VMEngine {}
+
 impl VMEngine {
-    pub fn new() -> Option<Self> {
-        unsafe {
-            das_initialize();
+    pub fn new() -> VMResult<Self> {
+        Self::builder().build()
+    }
+
+    /// Create an engine resolving modules through a `.das_project` file
+    pub fn with_project(project: &str) -> VMResult<Self> {
+        Self::builder().project(project).build()
+    }
+
+    pub fn with_file_access(access: VMFileAccess) -> VMResult<Self> {
+        Self::builder().file_access(access).build()
+    }
+
+    /// Configure an engine before creating it
+    pub fn builder() -> VMEngineBuilder {
+        VMEngineBuilder::new()
+    }
+
+    /// Compile and register a program, `VMError::DuplicateProgram` if `path` is already loaded
+    pub fn load(&mut self, path: &str) -> VMResult<Arc<VMProgram>> {
+        self.load_with(path, self.policies)
+    }
+
+    /// Like `load`, compiling under `policies` instead of the engine's.
+    /// Reloads of the program keep them.
+    pub fn load_with(&mut self, path: &str, policies: CompilePolicies) -> VMResult<Arc<VMProgram>> {
+        if self.sys_progs.contains_key(path) {
+            return Err(VMError::DuplicateProgram(path.to_string()));
+        }
+        let compiled = unsafe { self.compile(path, &policies, self.files())? };
+        Ok(self.register(path, compiled, policies))
+    }
+
+    /// Compile `path` and put it in place of the program loaded from it, if any.
+    ///
+    /// The old program is unloaded only once the new one compiled, and its policies carry over.
+    /// Unlike `reload`, its contexts are released rather than re-hosted.
+    pub fn replace(&mut self, path: &str) -> VMResult<Arc<VMProgram>> {
+        let policies = self
+            .sys_progs
+            .get(path)
+            .map_or(self.policies, |prog| prog.policies);
+        let compiled = unsafe { self.compile(path, &policies, self.files())? };
+        if let Some(old) = self.sys_progs.remove(path) {
+            self.detach(&old);
+        }
+        Ok(self.register(path, compiled, policies))
+    }
+
+    /// Forget the program loaded from `path` and release every context hosted from it.
+    /// Handles to it that are still around can no longer host.
+    pub fn unload(&mut self, path: &str) -> VMResult<()> {
+        let prog = self
+            .sys_progs
+            .remove(path)
+            .ok_or_else(|| VMError::ProgramNotFound(path.to_string()))?;
+        self.detach(&prog);
+        Ok(())
+    }
+
+    /// The program loaded from `path`
+    pub fn get(&self, path: &str) -> Option<Arc<VMProgram>> {
+        self.sys_progs.get(path).cloned()
+    }
+
+    /// Every loaded program, in no particular order
+    pub fn programs(&self) -> impl Iterator<Item = &Arc<VMProgram>> {
+        self.sys_progs.values()
+    }
+
+    fn register(
+        &mut self,
+        path: &str,
+        compiled: VMCompiled,
+        policies: CompilePolicies,
+    ) -> Arc<VMProgram> {
+        let prog = Arc::new(VMProgram {
+            state: self.state.clone(),
+            path: path.to_string(),
+            policies,
+            compiled: RwLock::new(Arc::new(compiled)),
+            detached: AtomicBool::new(false),
+        });
+        self.sys_progs.insert(path.to_string(), prog.clone());
+        prog
+    }
+
+    /// Stop `prog` from hosting and release the contexts it hosted
+    fn detach(&self, prog: &VMProgram) {
+        debug!("VM: Unloading program: {}", prog.path);
+        // under the state lock, a context being hosted is either tracked already or refused
+        let hosted = {
+            let sref = self.state.write();
+            prog.detached.store(true, Ordering::Release);
+            sref.hosted(&prog.compiled.read())
+        };
+        for mut ctx in hosted {
+            ctx.release();
+        }
+    }
+
+    /// The file access compiles currently read through
+    fn files(&self) -> Arc<VMFiles> {
+        self.core.files.lock().clone()
+    }
+
+    /// A fresh file access holding the in-memory files, `introduced` on top
+    unsafe fn fork_files(&self, introduced: &[(&str, &str)]) -> VMResult<Arc<VMFiles>> {
+        let mut texts = self.files().texts.clone();
+        for (name, code) in introduced {
+            let c_code =
+                CString::new(*code).map_err(|_| VMError::InvalidString(name.to_string()))?;
+            texts.insert(name.to_string(), Arc::new(c_code));
+        }
+        VMFiles::make(&self.core.access, texts, self.modules.keys()).map(Arc::new)
+    }
+
+    /// Compile `path` into its own module group holding the host modules,
+    /// so modules it `require`s are compiled fresh every time
+    unsafe fn compile(
+        &self,
+        script_path: &str,
+        policies: &CompilePolicies,
+        files: Arc<VMFiles>,
+    ) -> VMResult<VMCompiled> {
+        let c_script_path =
+            CString::new(script_path).map_err(|_| VMError::InvalidPath(script_path.to_string()))?;
+
+        let _env = runtime::environment();
+        debug!("VM: Creating program module group");
+        let das_libs = das_modulegroup_make();
+        if das_libs.is_null() {
+            return Err(VMError::Init("module group"));
+        }
+        for module in self.modules.values() {
+            das_modulegroup_add_module(das_libs, *module);
+        }
+
+        debug!("VM: Compiling program: {}", script_path);
+        let program = dasx_program_compile(
+            c_script_path.as_ptr().cast_mut(),
+            files.das_fs,
+            self.das_tout,
+            das_libs,
+            &policies.raw(),
+        );
+
+        // Check for compilation errors
+        let diagnostics = Diagnostic::collect(program);
+        if !diagnostics.is_empty() {
+            debug!("VM: Compilation failed with {} errors", diagnostics.len());
+        }
 
-            debug!("VM: Creating file access");
-            let das_fs = das_fileaccess_make_default();
-            if das_fs.is_null() {
-                error!("VM: Failed to create file access");
-                return None;
+        if program.is_null() || !diagnostics.is_empty() {
+            debug!("VM: Failed to compile program");
+            if !program.is_null() {
+                das_program_release(program);
             }
+            das_modulegroup_release(das_libs);
+            Err(VMError::Compile {
+                path: script_path.to_string(),
+                diagnostics,
+            })
+        } else {
+            Ok(VMCompiled {
+                program,
+                das_libs,
+                sources: reload::scan_sources(script_path, &files.texts, &self.core.access),
+                _files: files,
+                _core: self.core.clone(),
+            })
+        }
+    }
+
+    /// Route compiler messages, and the output of contexts hosted from now on, to `output`
+    pub fn set_output(&mut self, output: impl VMOutput + 'static) -> VMResult<()> {
+        let output: SharedOutput = Arc::new(output);
+        let tout_output = OutputHandle::new(output.clone());
 
-            debug!("VM: Creating text output");
-            let das_tout = das_text_make_printer();
+        unsafe {
+            debug!("VM: Replacing text output");
+            let das_tout = tout_output.make_writer();
             if das_tout.is_null() {
-                error!("VM: Failed to create text output");
-                das_fileaccess_release(das_fs);
-                return None;
+                return Err(VMError::Init("text output"));
             }
+            das_text_release(self.das_tout);
+            self.das_tout = das_tout;
+        }
+        self.tout_output = tout_output;
+        self.state.write().output = output;
+        Ok(())
+    }
 
-            debug!("VM: Creating module group");
-            let das_libs = das_modulegroup_make();
-            if das_libs.is_null() {
-                error!("VM: Failed to create module group");
-                das_fileaccess_release(das_fs);
-                das_text_release(das_tout);
-                return None;
-            }
+    /// Register a host module scripts can `require`.
+    ///
+    /// Bind everything before loading the programs that use it,
+    /// already compiled programs do not see later additions.
+    pub fn module(&mut self, name: &str) -> VMResult<VMModuleBuilder<'_>> {
+        if self.modules.contains_key(name) {
+            return Err(VMError::DuplicateModule(name.to_string()));
+        }
+        let builder = VMModuleBuilder::new(name, self.core.das_libs, &mut self.enumerations)?;
+        if let VMFileAccess::Sandboxed(_) = self.core.access {
+            let c_name =
+                CString::new(name).map_err(|_| VMError::InvalidString(name.to_string()))?;
+            let das_fs = self.core.files.lock().das_fs;
+            unsafe { dasx_fileaccess_sandbox_allow(das_fs, c_name.as_ptr()) };
+        }
+        self.modules.insert(name.to_string(), builder.module);
+        Ok(builder)
+    }
 
-            let state = VMState {
-                hanged: false,
-                tracked: Vec::new(),
-            };
+    /// Introduce an in-memory file, visible to later compiles and `require`.
+    ///
+    /// `require foo` resolves to `foo.das` next to the requiring file,
+    /// so name modules the way they would sit on disk.
+    ///
+    /// Goes into a fresh file access, programs compiled before keep reading the previous content.
+    pub fn introduce(&mut self, name: &str, code: &str) -> VMResult<()> {
+        self.introduce_all(&[(name, code)])
+    }
 
-            Some(Self {
-                das_fs,
-                das_tout,
-                das_libs,
-                state: Arc::new(RwLock::new(state)),
-                sys_progs: HashMap::new(),
-            })
+    /// `introduce` several files at once, into one fresh file access
+    fn introduce_all(&mut self, files: &[(&str, &str)]) -> VMResult<()> {
+        for (name, _) in files {
+            debug!("VM: Introducing file: {}", name);
         }
+        let files = unsafe { self.fork_files(files)? };
+        *self.core.files.lock() = files;
+        Ok(())
     }
 
-    pub fn load(&mut self, path: &str) -> Option<Arc<VMProgram>> {
-        if let Some(prog) = VMProgram::new(
-            self.state.clone(),
-            path,
-            self.das_fs,
-            self.das_tout,
-            self.das_libs,
-        ) {
-            self.sys_progs.insert(path.to_string(), Arc::new(prog));
-            self.sys_progs.get(path).map(|f| f.clone())
-        } else {
-            None
+    /// Compile a program from source text registered under `name`
+    pub fn load_source(&mut self, name: &str, code: &str) -> VMResult<Arc<VMProgram>> {
+        // checked up front, introducing would swap the source of the loaded program
+        if self.sys_progs.contains_key(name) {
+            return Err(VMError::DuplicateProgram(name.to_string()));
         }
+        self.introduce(name, code)?;
+        self.load(name)
+    }
+
+    /// Introduce every file in `files`, then compile `main` out of them
+    pub fn load_sources(&mut self, main: &str, files: &[(&str, &str)]) -> VMResult<Arc<VMProgram>> {
+        if self.sys_progs.contains_key(main) {
+            return Err(VMError::DuplicateProgram(main.to_string()));
+        }
+        self.introduce_all(files)?;
+        self.load(main)
     }
 }
 
-#[cfg(feature = "free")]
+/// Releases the hosted contexts and drops the engine's programs.
+/// Programs still held elsewhere keep the core, and with it the runtime, alive.
 impl Drop for VMEngine {
     fn drop(&mut self) {
         let mut sref = self.state.write();
         if !sref.hanged {
             sref.hanged = true;
-            unsafe {
-                debug!("VM: DANGERously VMEngine is memdropped");
+            debug!("VM: VMEngine is dropped");
 
-                das_fileaccess_release(self.das_fs);
-                das_text_release(self.das_tout);
-                das_modulegroup_release(self.das_libs);
-
-                for ctx in &mut sref.tracked {
-                    ctx.release();
-                }
-
-                self.sys_progs.clear();
+            for mut ctx in sref.live().collect::<Vec<_>>() {
+                ctx.release();
+            }
+            sref.tracked.clear();
+            for prog in self.sys_progs.values() {
+                prog.detached.store(true, Ordering::Release);
+            }
+            self.sys_progs.clear();
 
-                das_shutdown();
+            unsafe {
+                das_text_release(self.das_tout);
             }
         } else {
             debug!("VM: VMEngine is flushed twice?");
@@ -133,79 +580,66 @@ impl Drop for VMEngine {
 /// The system to load a program and compile it, prepared for context hosting
 pub struct VMProgram {
     state: Arc<RwLock<VMState>>,
+    path: String,
+    policies: CompilePolicies,
+    /// Swapped in place by `VMEngine::reload`, contexts hold on to the one they run
+    compiled: RwLock<Arc<VMCompiled>>,
+    /// Set once the engine unloaded, replaced or dropped it
+    detached: AtomicBool,
+}
+
+/// A compiled program with the module group it was compiled against
+struct VMCompiled {
     program: *mut das_program,
+    das_libs: *mut das_module_group,
+    sources: Vec<reload::SourceStamp>,
+    /// What the program was compiled through, its line info points in there
+    _files: Arc<VMFiles>,
+    /// Released after the program
+    _core: Arc<VMCore>,
 }
 
 impl VMProgram {
-    /// Creates a new DaScriptExecutable from the given script path.
-    fn new(
-        state: Arc<RwLock<VMState>>,
-        script_path: &str,
-        das_fs: *mut das_file_access,
-        das_tout: *mut das_text_writer,
-        das_libs: *mut das_module_group,
-    ) -> Option<Self> {
-        let c_script_path = match CString::new(script_path) {
-            Ok(s) => s,
-            Err(_) => {
-                error!("Invalid string path");
-                return None;
-            }
-        };
+    /// Path, or in-memory name, the program was loaded from
+    pub fn path(&self) -> &str {
+        &self.path
+    }
 
-        unsafe {
-            debug!("VM: Compiling program: {}", script_path);
-            let program = das_program_compile(
-                c_script_path.as_ptr().cast_mut(),
-                das_fs,
-                das_tout,
-                das_libs,
-            );
-
-            // Check for compilation errors
-            let err_count = if !program.is_null() {
-                das_program_err_count(program)
-            } else {
-                0
-            };
-
-            if err_count > 0 {
-                debug!("VM: Compilation failed with {} errors", err_count);
-                for i in 0..err_count {
-                    let error = das_program_get_error(program, i);
-                    if !error.is_null() {
-                        das_error_output(error, das_tout);
-                    }
-                }
-            }
+    /// What the program was compiled under
+    pub fn policies(&self) -> &CompilePolicies {
+        &self.policies
+    }
 
-            // do not release global object
-            // debug!("VM: Finished compilation, releasing");
-            // das_fileaccess_release(das_fs);
-            // das_modulegroup_release(das_libs);
-            // das_text_release(das_tout);
-
-            if program.is_null() {
-                error!("VM: Failed to compile program");
-                None
-            } else {
-                Some(VMProgram { state, program })
-            }
-        }
+    /// Whether the engine still has this program loaded
+    pub fn is_loaded(&self) -> bool {
+        !self.detached.load(Ordering::Acquire)
     }
 
     /// Hosts the compiled program and returns a VMContext.
-    pub fn host(&self) -> Option<VMHangedLock<VMContext>> {
-        VMContext::new(self.state.clone(), self.program)
+    pub fn host(&self) -> VMResult<VMHangedLock<VMContext>> {
+        self.host_with(VMHostOptions::default())
+    }
+
+    /// Like `host`, tuning the context with `options`
+    pub fn host_with(&self, options: VMHostOptions) -> VMResult<VMHangedLock<VMContext>> {
+        if !self.is_loaded() {
+            return Err(VMError::ProgramNotFound(self.path.clone()));
+        }
+        VMContext::new(self, options)
     }
 }
 
-#[cfg(feature = "free")]
-impl Drop for VMProgram {
+// The program is only read after compiling, simulated under the environment lock
+unsafe impl Send for VMCompiled {}
+unsafe impl Sync for VMCompiled {}
+
+impl Drop for VMCompiled {
     fn drop(&mut self) {
+        let _env = runtime::environment();
         unsafe {
             debug!("VM: Releasing program");
             das_program_release(self.program);
+            das_modulegroup_release(self.das_libs);
         }
     }
 }
@@ -228,6 +662,18 @@ impl<T: VMHang> VMHangedLock<T> {
             wref.hanged = None;
         }
     }
+
+    /// Whether the object is still there, engine teardown releases it
+    pub fn is_alive(&self) -> bool {
+        self.0.read().hanged.is_some()
+    }
+
+    /// Run `f` on the object, `VMError::Destroyed` once it has been released.
+    /// Takes the lock exclusively, so calls from several threads never overlap.
+    fn with<R>(&self, f: impl FnOnce(&T) -> VMResult<R>) -> VMResult<R> {
+        let lockref = self.0.write();
+        f(lockref.hanged.as_deref().ok_or(VMError::Destroyed)?)
+    }
 }
 
 impl<T: VMHang> Clone for VMHangedLock<T> {
@@ -239,121 +685,269 @@ impl<T: VMHang> Clone for VMHangedLock<T> {
 /// A context hosted from `VMProgram`, manage the context
 pub struct VMContext {
     context: *mut das_context,
+    /// Bumped every time reload swaps the native context, for `VMFunction`
+    generation: u64,
+    /// What it was hosted with, kept when re-hosted on reload
+    options: VMHostOptions,
+    /// Stops calls for budgets and `VMCancel`
+    interrupt: Arc<interrupt::Interrupt>,
+    /// Where the context's print output goes, toggled for captured calls
+    output: Arc<ContextOutput>,
+    /// Backs `output` for native code, dropped after the context is released
+    _output: OutputHandle,
     // tout: *mut das_text_writer,
+    /// The program simulated into this context, kept alive until it is released
+    compiled: Arc<VMCompiled>,
 }
 
 impl VMContext {
     /// Creates a new VMContext
-    fn new(state: Arc<RwLock<VMState>>, program: *mut das_program) -> Option<VMHangedLock<Self>> {
-        unsafe {
-            debug!("VM: Creating context");
-            let context = das_context_make(das_program_context_stack_size(program));
-            if context.is_null() {
-                error!("VM: Failed to create context");
-                return None;
-            }
+    fn new(prog: &VMProgram, options: VMHostOptions) -> VMResult<VMHangedLock<Self>> {
+        let (output, stack_size) = {
+            let sref = prog.state.read();
+            (sref.output.clone(), sref.stack_size)
+        };
+        let compiled = prog.compiled.read().clone();
+        let hanging =
+            VMHangedLock::new(unsafe { Self::simulate(compiled, output, options, stack_size)? });
+
+        // checked again under the lock `detach` flags the program with
+        let mut sref = prog.state.write();
+        if !prog.is_loaded() {
+            drop(sref);
+            return Err(VMError::ProgramNotFound(prog.path.clone()));
+        }
+        sref.track(&hanging);
+        Ok(hanging)
+    }
 
-            debug!("VM: Creating text output");
-            let tout = das_text_make_printer();
-            if tout.is_null() {
-                error!("VM: Failed to create text output");
-                das_context_release(context);
-                return None;
-            }
+    /// Simulates `compiled` into a fresh context, `stack_size` is the engine default
+    unsafe fn simulate(
+        compiled: Arc<VMCompiled>,
+        sink: SharedOutput,
+        options: VMHostOptions,
+        stack_size: Option<usize>,
+    ) -> VMResult<Self> {
+        let program = compiled.program;
+        let _env = runtime::environment();
+        let stack = options.stack(stack_size, das_program_context_stack_size(program))?;
+        let context_output = Arc::new(ContextOutput::new(sink));
+        let output = OutputHandle::new(context_output.clone());
+
+        debug!("VM: Creating context");
+        let context = output.make_context(stack);
+        if context.is_null() {
+            return Err(VMError::Init("context"));
+        }
 
-            debug!("VM: Simulating program");
-            if das_program_simulate(program, context, tout) == 0 {
-                error!("VM: Simulation failed");
-                let err_count = das_program_err_count(program);
-                for i in 0..err_count {
-                    let error = das_program_get_error(program, i);
-                    if !error.is_null() {
-                        das_error_output(error, tout);
-                    }
-                }
-                das_text_release(tout);
-                None
-            } else {
-                das_text_release(tout);
-                let hanging = VMHangedLock::new(VMContext {
-                    context,
-                    // tout
-                });
-                state.write().tracked.push(hanging.clone());
-                Some(hanging)
-            }
+        debug!("VM: Creating text output");
+        let tout = output.make_writer();
+        if tout.is_null() {
+            das_context_release(context);
+            return Err(VMError::Init("text output"));
+        }
+
+        debug!("VM: Simulating program");
+        if das_program_simulate(program, context, tout) == 0 {
+            debug!("VM: Simulation failed");
+            let diagnostics = Diagnostic::collect(program);
+            das_text_release(tout);
+            das_context_release(context);
+            Err(VMError::Simulation(diagnostics))
+        } else {
+            das_text_release(tout);
+            Self::limit_heaps(context, &options);
+            Ok(VMContext {
+                context,
+                generation: 0,
+                interrupt: Arc::new(interrupt::Interrupt::new(context, options.time_budget)),
+                options,
+                output: context_output,
+                _output: output,
+                // tout
+                compiled,
+            })
         }
     }
 }
 
 impl VMHangedLock<VMContext> {
     /// Find and evaluate a function by name
-    pub fn eval_function(&self, name: &str) -> bool {
-        debug!("VM: Evaluating function '{}'", name);
-
-        // weird lifetime and scope hacking just to get the rawptr
-        let lockref = self.0.read();
-        let vmctx = match &lockref.hanged {
-            Some(inner) => inner,
-            None => panic!("unwrap failed"),
-        };
+    pub fn eval_function(&self, name: &str) -> VMResult<()> {
+        self.eval_function_args(name, &[])
+    }
 
+    /// Find and evaluate a function by name, passing typed arguments
+    pub fn eval_function_args(&self, name: &str, args: &[VMArg]) -> VMResult<()> {
+        self.eval_raw(name, args, VMType::Void).map(|_| ())
+    }
 
-        unsafe {
-            let c_name = match CString::new(name) {
-                Ok(s) => s,
-                Err(_) => {
-                    error!("Invalid function name");
-                    return false;
+    /// Find and evaluate a function by name, decoding its result as `ret`
+    pub fn call_function(&self, name: &str, args: &[VMArg], ret: VMType) -> VMResult<VMValue> {
+        let mut result = self.eval_raw(name, args, ret)?;
+        debug!("VM: Decoding result as {:?}", ret);
+        Ok(unsafe { VMValue::decode(&mut result, ret) })
+    }
+
+    /// Evaluate and hand back the raw result slot
+    fn eval_raw(&self, name: &str, args: &[VMArg], ret: VMType) -> VMResult<V4FloatUnlined> {
+        debug!(
+            "VM: Evaluating function '{}' with {} args",
+            name,
+            args.len()
+        );
+
+        self.with(|vmctx| unsafe { vmctx.eval_named(name, args, ret) })
+    }
+
+    /// Like `eval_function_args`, also returning what the script printed during the call
+    pub fn eval_function_captured(&self, name: &str, args: &[VMArg]) -> VMResult<String> {
+        self.captured(|vmctx| unsafe { vmctx.eval_named(name, args, VMType::Void) })
+            .map(|(_, text)| text)
+    }
+
+    /// Like `call_function`, also returning what the script printed during the call
+    pub fn call_function_captured(
+        &self,
+        name: &str,
+        args: &[VMArg],
+        ret: VMType,
+    ) -> VMResult<(VMValue, String)> {
+        self.captured(|vmctx| unsafe {
+            let mut result = vmctx.eval_named(name, args, ret)?;
+            Ok(VMValue::decode(&mut result, ret))
+        })
+    }
+
+    /// Run `call` with the context's prints diverted into a string.
+    /// On failure the text goes on to the sink so it is not lost.
+    ///
+    /// All under one lock, another call cannot print into, or end, the capture.
+    fn captured<T>(&self, call: impl FnOnce(&VMContext) -> VMResult<T>) -> VMResult<(T, String)> {
+        self.with(|vmctx| {
+            let outer = vmctx.output.begin();
+            let result = call(vmctx);
+            let text = vmctx.output.end(outer);
+            match result {
+                Ok(value) => Ok((value, text)),
+                Err(e) => {
+                    vmctx.output.forward(&text);
+                    Err(e)
                 }
-            };
-            debug!("VM: Finding function pointer");
-            let function = das_context_find_function(vmctx.context, c_name.as_ptr().cast_mut());
-            if function.is_null() {
-                error!("Function '{}' not found", name);
-                return false;
             }
+        })
+    }
 
-            // debug!("EXT: Validate function pointer");
-            // if !dasx_verif_fn(function, c_name.into_raw()) {
-            //     error!("Pointer is unsanitized");
-            //     return false;
-            // }
-
-            debug!("VM: Evaluating function with catch");
-            // let mut nullptr_allocated = [0f32, 0f32, 0f32, 0f32];
-
-            let mut args = V4FloatUnlined::default();
-            let mut ret = V4FloatUnlined::default();
-
-            das_context_eval_with_catch_unaligned(
-                vmctx.context,
-                function,
-                args.raw(),
-                0,
-                ret.raw(),
-            );
-            let exception = das_context_get_exception(vmctx.context);
-            if !exception.is_null() {
-                if let Ok(ex_str) = std::ffi::CStr::from_ptr(exception).to_str() {
-                    error!("Exception while evaluating '{}': {}", name, ex_str);
-                } else {
-                    error!(
-                        "Exception while evaluating '{}' (invalid UTF-8 in exception)",
-                        name
-                    );
-                }
-                return false;
+    /// Resolve `name` once into a handle that can be called repeatedly
+    pub fn function(&self, name: &str) -> VMResult<VMFunction> {
+        VMFunction::new(self.clone(), name)
+    }
+}
+
+impl VMContext {
+    unsafe fn eval_named(
+        &self,
+        name: &str,
+        args: &[VMArg],
+        ret: VMType,
+    ) -> VMResult<V4FloatUnlined> {
+        let function = self.find_function(name)?;
+        self.eval(function, name, args, ret)
+    }
+
+    unsafe fn find_function(&self, name: &str) -> VMResult<*mut das_function> {
+        let c_name = CString::new(name).map_err(|_| VMError::InvalidString(name.to_string()))?;
+        debug!("VM: Finding function pointer");
+        let function = das_context_find_function(self.context, c_name.as_ptr().cast_mut());
+        if function.is_null() {
+            return Err(VMError::FunctionNotFound(name.to_string()));
+        }
+
+        // debug!("EXT: Validate function pointer");
+        // if !dasx_verif_fn(function, c_name.into_raw()) {
+        //     error!("Pointer is unsanitized");
+        //     return false;
+        // }
+
+        Ok(function)
+    }
+
+    /// Evaluate a function resolved from this context, `name` is for errors only.
+    /// The result slot is only handed back when it holds a `ret`, `VMType::Void` takes any.
+    unsafe fn eval(
+        &self,
+        function: *mut das_function,
+        name: &str,
+        args: &[VMArg],
+        ret: VMType,
+    ) -> VMResult<V4FloatUnlined> {
+        let signature = VMSignature::of(function).ok_or_else(|| VMError::Signature {
+            function: name.to_string(),
+            reason: "has no debug info to check the call against".to_string(),
+        })?;
+        signature
+            .check_args(args)
+            .and_then(|()| signature.check_result(ret))
+            .map_err(|reason| VMError::Signature {
+                function: name.to_string(),
+                reason,
+            })?;
+
+        debug!("VM: Marshalling arguments");
+        let mut args = VMArgs::new(args)
+            .map_err(|e| VMError::InvalidString(String::from_utf8_lossy(&e.into_vec()).into()))?;
+        let mut ret = V4FloatUnlined::default();
+
+        self.take_interrupt();
+        let armed = self
+            .interrupt
+            .budget()
+            .map(|budget| (budget, interrupt::arm(&self.interrupt, budget)));
+
+        debug!("VM: Evaluating function with catch");
+        runtime::bind();
+        self.interrupt.set_running(true);
+        das_context_eval_with_catch_unaligned(
+            self.context,
+            function,
+            args.raw(),
+            args.len(),
+            ret.raw(),
+        );
+        // before disarming, the watchdog must not stop a call that returned
+        self.interrupt.set_running(false);
+        let budget = armed.map(|(budget, _)| budget);
+
+        match self.take_interrupt() {
+            interrupt::TIMEOUT => {
+                return Err(VMError::Timeout {
+                    function: name.to_string(),
+                    budget: budget.unwrap_or_default(),
+                })
             }
-            debug!("VM: Function evaluation completed successfully");
-            true
+            interrupt::CANCELLED => return Err(VMError::Cancelled(name.to_string())),
+            _ => {}
+        }
+        let exception = das_context_get_exception(self.context);
+        if !exception.is_null() {
+            return Err(VMError::Exception {
+                function: name.to_string(),
+                message: CStr::from_ptr(exception).to_string_lossy().into_owned(),
+            });
         }
+        debug!("VM: Function evaluation completed successfully");
+        Ok(ret)
     }
 }
 
-#[cfg(feature = "free")]
+// Used only through `VMHangedLock::with`, one thread at a time
+unsafe impl Send for VMContext {}
+unsafe impl Sync for VMContext {}
+
 impl Drop for VMContext {
     fn drop(&mut self) {
+        self.interrupt.detach(self.context);
+        let _env = runtime::environment();
         unsafe {
             debug!("VM: Releasing context ctx");
             das_context_release(self.context);
@@ -365,24 +959,6 @@ impl Drop for VMContext {
 
 impl VMHang for VMContext {}
 
-#[no_mangle]
-/// Initialize daScript runtime
-pub extern "C" fn engine_initialize() {
-    info!("VM: Initializing engine");
-    unsafe {
-        das_initialize();
-    }
-}
-
-#[no_mangle]
-/// Shutdown daScript runtime
-pub extern "C" fn engine_shutdown() {
-    info!("VM: Shutting down engine");
-    unsafe {
-        das_shutdown();
-    }
-}
-
 // #[no_mangle]
 // /// Load program into context
 // pub extern "C" fn engine_load_program(
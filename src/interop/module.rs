@@ -0,0 +1,423 @@
+//! Host modules, exposing Rust functions to scripts
+//!
+//! daScript calls bound functions through a plain C pointer with no user data,
+//! so each function gets its own monomorphized trampoline. That only works for
+//! zero-sized callables: `fn` items and closures that capture nothing.
+
+use super::{
+    extended::dasx_context_throw, runtime, DasEnumeration, DasStructure, VMError, VMResult,
+};
+use crate::bindings::das::{
+    das_argument_double_unaligned, das_argument_float_unaligned, das_argument_int_unaligned,
+    das_argument_ptr_unaligned, das_argument_string_unaligned, das_context,
+    das_enumeration_add_value, das_enumeration_make, das_module, das_module_bind_enumeration,
+    das_module_bind_interop_function_unaligned, das_module_bind_structure, das_module_create,
+    das_module_group, das_modulegroup_add_module, das_node, das_result_double_unaligned,
+    das_result_float_unaligned, das_result_int_unaligned, das_result_ptr_unaligned,
+    das_result_string_unaligned, das_result_void_unaligned, das_structure_add_field,
+    das_structure_make, vec4f_unaligned, SIDEEFFECTS_modifyExternal,
+};
+use log::debug;
+use std::{
+    any::Any,
+    collections::HashSet,
+    ffi::{c_void, CStr, CString},
+    io::Write,
+    mem::{align_of, size_of},
+    panic::{self, AssertUnwindSafe},
+    ptr::NonNull,
+};
+
+/// A Rust type that can be read from a script argument slot
+pub trait FromDasArg: Sized {
+    /// daScript mangled type name
+    const MANGLED: &'static str;
+
+    /// # Safety
+    /// `arg` must point to a slot written by daScript for this type
+    unsafe fn from_arg(arg: *mut vec4f_unaligned) -> Self;
+}
+
+/// A Rust type that can be written into a script result slot
+pub trait IntoDasResult {
+    /// daScript mangled type name
+    const MANGLED: &'static str;
+
+    /// # Safety
+    /// `result` must point to the result slot of the current call
+    unsafe fn into_result(self, result: *mut vec4f_unaligned);
+}
+
+macro_rules! das_scalar {
+    ($ty:ty, $mangled:literal, $arg:ident, $res:ident) => {
+        impl FromDasArg for $ty {
+            const MANGLED: &'static str = $mangled;
+
+            unsafe fn from_arg(arg: *mut vec4f_unaligned) -> Self {
+                $arg(arg)
+            }
+        }
+
+        impl IntoDasResult for $ty {
+            const MANGLED: &'static str = $mangled;
+
+            unsafe fn into_result(self, result: *mut vec4f_unaligned) {
+                $res(result, self)
+            }
+        }
+    };
+}
+
+das_scalar!(
+    i32,
+    "i",
+    das_argument_int_unaligned,
+    das_result_int_unaligned
+);
+das_scalar!(
+    f32,
+    "f",
+    das_argument_float_unaligned,
+    das_result_float_unaligned
+);
+das_scalar!(
+    f64,
+    "d",
+    das_argument_double_unaligned,
+    das_result_double_unaligned
+);
+das_scalar!(
+    *mut c_void,
+    "?",
+    das_argument_ptr_unaligned,
+    das_result_ptr_unaligned
+);
+
+/// Script strings are copied, daScript passes the empty string as null
+impl FromDasArg for String {
+    const MANGLED: &'static str = "s";
+
+    unsafe fn from_arg(arg: *mut vec4f_unaligned) -> Self {
+        let ptr = das_argument_string_unaligned(arg);
+        if ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        }
+    }
+}
+
+/// Only static strings can be returned, the script keeps the pointer
+impl IntoDasResult for &'static CStr {
+    const MANGLED: &'static str = "s";
+
+    unsafe fn into_result(self, result: *mut vec4f_unaligned) {
+        das_result_string_unaligned(result, self.as_ptr().cast_mut())
+    }
+}
+
+/// Values a script cast from a number may be unknown, those decode to `None`
+impl<T: DasEnumeration> FromDasArg for Option<T> {
+    const MANGLED: &'static str = T::MANGLED;
+
+    unsafe fn from_arg(arg: *mut vec4f_unaligned) -> Self {
+        T::from_int(das_argument_int_unaligned(arg))
+    }
+}
+
+impl IntoDasResult for () {
+    const MANGLED: &'static str = "v";
+
+    unsafe fn into_result(self, result: *mut vec4f_unaligned) {
+        das_result_void_unaligned(result)
+    }
+}
+
+/// A Rust callable that can be bound as a script function
+pub trait HostFunction<Args>: Copy + 'static {
+    /// daScript signature, result type first then each argument
+    fn signature() -> String;
+
+    /// # Safety
+    /// `args` must hold one slot per argument, `result` must be writable
+    unsafe fn invoke(self, args: *mut vec4f_unaligned, result: *mut vec4f_unaligned);
+}
+
+macro_rules! host_function {
+    ($($arg:ident),*) => {
+        impl<Func, Ret, $($arg),*> HostFunction<($($arg,)*)> for Func
+        where
+            Func: Fn($($arg),*) -> Ret + Copy + 'static,
+            Ret: IntoDasResult,
+            $($arg: FromDasArg,)*
+        {
+            fn signature() -> String {
+                [Ret::MANGLED $(, $arg::MANGLED)*].join(" ")
+            }
+
+            #[allow(unused_variables, unused_mut, unused_assignments)]
+            unsafe fn invoke(self, args: *mut vec4f_unaligned, result: *mut vec4f_unaligned) {
+                let mut index = 0;
+                $(
+                    #[allow(non_snake_case)]
+                    let $arg = $arg::from_arg(args.add(index));
+                    index += 1;
+                )*
+                self($($arg),*).into_result(result)
+            }
+        }
+    };
+}
+
+host_function!();
+host_function!(A1);
+host_function!(A1, A2);
+host_function!(A1, A2, A3);
+host_function!(A1, A2, A3, A4);
+host_function!(A1, A2, A3, A4, A5);
+host_function!(A1, A2, A3, A4, A5, A6);
+
+/// Room for the message of a panic raised into the script, longer ones are cut
+const PANIC_MESSAGE: usize = 256;
+
+/// The C entry point daScript calls, conjures the zero-sized callable back.
+/// A panic becomes a script exception instead of unwinding into daScript.
+unsafe extern "C" fn trampoline<F, Args>(
+    ctx: *mut das_context,
+    _node: *mut das_node,
+    args: *mut vec4f_unaligned,
+    result: *mut vec4f_unaligned,
+) where
+    F: HostFunction<Args>,
+{
+    // `function` rejects anything that is not zero-sized, so there is nothing to read
+    let func = NonNull::<F>::dangling().as_ptr().read();
+
+    let mut message = [0u8; PANIC_MESSAGE];
+    let panicked = match panic::catch_unwind(AssertUnwindSafe(|| func.invoke(args, result))) {
+        Ok(()) => false,
+        Err(payload) => {
+            describe_panic(payload.as_ref(), &mut message);
+            true
+        }
+    };
+    if panicked {
+        // the throw longjmps out of this frame, nothing in it may need dropping by now
+        dasx_context_throw(ctx, message.as_ptr().cast());
+    }
+}
+
+/// Write the panic message into `buffer`, nul terminated
+fn describe_panic(payload: &(dyn Any + Send), buffer: &mut [u8]) {
+    let text = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string payload");
+    let mut out = &mut buffer[..PANIC_MESSAGE - 1];
+    // running out of room only cuts the message short
+    let _ = write!(out, "host function panicked: {}", text.replace('\0', ""));
+}
+
+/// Builds a native module scripts can `require`, registered on the engine's module group
+pub struct VMModuleBuilder<'a> {
+    name: String,
+    pub(super) module: *mut das_module,
+    das_libs: *mut das_module_group,
+    /// The engine's bound enumerations, functions mangle theirs by name
+    enumerations: &'a mut HashSet<String>,
+}
+
+impl<'a> VMModuleBuilder<'a> {
+    pub(crate) fn new(
+        name: &str,
+        das_libs: *mut das_module_group,
+        enumerations: &'a mut HashSet<String>,
+    ) -> VMResult<Self> {
+        let c_name = CString::new(name).map_err(|_| VMError::InvalidString(name.to_string()))?;
+
+        unsafe {
+            let _env = runtime::environment();
+            debug!("VM: Creating module: {}", name);
+            let module = das_module_create(c_name.as_ptr().cast_mut());
+            if module.is_null() {
+                return Err(VMError::Init("module"));
+            }
+            das_modulegroup_add_module(das_libs, module);
+
+            Ok(Self {
+                name: name.to_string(),
+                module,
+                das_libs,
+                enumerations,
+            })
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Bind a Rust function under `name`.
+    ///
+    /// Enumerations it takes or returns have to be bound first, by any host module.
+    /// A panic inside `func` is raised in the script as an exception,
+    /// the call from the host then fails with `VMError::Exception`.
+    pub fn function<F, Args>(&mut self, name: &str, func: F) -> VMResult<&mut Self>
+    where
+        F: HostFunction<Args>,
+    {
+        if size_of::<F>() != 0 {
+            return Err(VMError::CapturingFunction(name.to_string()));
+        }
+        // the value is recreated inside the trampoline
+        let _ = func;
+
+        let c_name = CString::new(name).map_err(|_| VMError::InvalidString(name.to_string()))?;
+        let signature = F::signature();
+        // daScript resolves `E<Name>` against the group and fails deep inside otherwise
+        if let Some(enumeration) = signature
+            .split_whitespace()
+            .filter_map(|ty| ty.strip_prefix("E<")?.strip_suffix('>'))
+            .find(|enumeration| !self.enumerations.contains(*enumeration))
+        {
+            return Err(VMError::UnboundEnumeration {
+                function: name.to_string(),
+                enumeration: enumeration.to_string(),
+            });
+        }
+        let c_signature = CString::new(signature.as_str())
+            .map_err(|_| VMError::InvalidString(signature.clone()))?;
+
+        unsafe {
+            let _env = runtime::environment();
+            debug!(
+                "VM: Binding function {}::{} ({})",
+                self.name, name, signature
+            );
+            das_module_bind_interop_function_unaligned(
+                self.module,
+                self.das_libs,
+                Some(trampoline::<F, Args>),
+                c_name.as_ptr().cast_mut(),
+                c_name.as_ptr().cast_mut(),
+                SIDEEFFECTS_modifyExternal,
+                c_signature.as_ptr().cast_mut(),
+            );
+        }
+        Ok(self)
+    }
+
+    /// Bind a `#[repr(C)]` struct, usually `#[derive(DasStructure)]`, as a handled type
+    pub fn structure<T: DasStructure>(&mut self) -> VMResult<&mut Self> {
+        let c_name =
+            CString::new(T::NAME).map_err(|_| VMError::InvalidString(T::NAME.to_string()))?;
+
+        unsafe {
+            let _env = runtime::environment();
+            debug!(
+                "VM: Binding structure {}::{} ({} bytes)",
+                self.name,
+                T::NAME,
+                size_of::<T>()
+            );
+            let st = das_structure_make(
+                self.das_libs,
+                c_name.as_ptr(),
+                c_name.as_ptr(),
+                size_of::<T>() as i32,
+                align_of::<T>() as i32,
+            );
+            if st.is_null() {
+                return Err(VMError::Init("structure"));
+            }
+
+            for field in T::fields() {
+                let c_field = CString::new(field.name)
+                    .map_err(|_| VMError::InvalidString(field.name.to_string()))?;
+                let c_type = CString::new(field.mangled)
+                    .map_err(|_| VMError::InvalidString(field.mangled.to_string()))?;
+                debug!(
+                    "VM: Adding field {}.{} at {} ({})",
+                    T::NAME,
+                    field.name,
+                    field.offset,
+                    field.mangled
+                );
+                das_structure_add_field(
+                    st,
+                    self.module,
+                    self.das_libs,
+                    c_field.as_ptr(),
+                    c_field.as_ptr(),
+                    field.offset as i32,
+                    c_type.as_ptr(),
+                );
+            }
+
+            das_module_bind_structure(self.module, st);
+        }
+        Ok(self)
+    }
+
+    /// Bind a fieldless enum, usually `#[derive(DasEnumeration)]`, as an enumeration
+    pub fn enumeration<T: DasEnumeration>(&mut self) -> VMResult<&mut Self> {
+        let c_name =
+            CString::new(T::NAME).map_err(|_| VMError::InvalidString(T::NAME.to_string()))?;
+
+        unsafe {
+            let _env = runtime::environment();
+            debug!("VM: Binding enumeration {}::{}", self.name, T::NAME);
+            // defined on the host side, hence external
+            let en = das_enumeration_make(c_name.as_ptr(), c_name.as_ptr(), 1);
+            if en.is_null() {
+                return Err(VMError::Init("enumeration"));
+            }
+
+            for (name, value) in T::values() {
+                let c_value =
+                    CString::new(name).map_err(|_| VMError::InvalidString(name.to_string()))?;
+                debug!("VM: Adding value {}::{} = {}", T::NAME, name, value);
+                das_enumeration_add_value(en, c_value.as_ptr(), c_value.as_ptr(), value);
+            }
+
+            das_module_bind_enumeration(self.module, en);
+        }
+        self.enumerations.insert(T::NAME.to_string());
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe_panic, PANIC_MESSAGE};
+    use std::ffi::CStr;
+
+    fn described(payload: Box<dyn std::any::Any + Send>) -> String {
+        let mut buffer = [0u8; PANIC_MESSAGE];
+        describe_panic(payload.as_ref(), &mut buffer);
+        CStr::from_bytes_until_nul(&buffer)
+            .expect("nul terminated")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn describes_str_and_string_payloads() {
+        assert_eq!(described(Box::new("boom")), "host function panicked: boom");
+        assert_eq!(
+            described(Box::new(format!("bad {}", 7))),
+            "host function panicked: bad 7"
+        );
+        assert_eq!(
+            described(Box::new(7)),
+            "host function panicked: non-string payload"
+        );
+    }
+
+    #[test]
+    fn cuts_long_messages_and_stays_terminated() {
+        let text = described(Box::new("x".repeat(PANIC_MESSAGE * 2)));
+        assert_eq!(text.len(), PANIC_MESSAGE - 1);
+    }
+}
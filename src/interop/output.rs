@@ -0,0 +1,264 @@
+//! Where script output goes
+//!
+//! Script `print`, script errors and compiler messages are handed to a
+//! `VMOutput` instead of going straight to stdout.
+
+use super::extended::{dasx_context_make_with_output, dasx_text_make_callback};
+use crate::bindings::das::{das_context, das_text_writer};
+use log::{error, info};
+use parking_lot::Mutex;
+use std::{
+    ffi::{c_char, c_int, c_void},
+    io::Write,
+    slice,
+    sync::Arc,
+};
+
+/// Receives text produced by scripts and the compiler.
+///
+/// Called from inside daScript, a panic here aborts the process.
+pub trait VMOutput: Send + Sync {
+    /// Script `print`
+    fn print(&self, text: &str);
+
+    /// Script errors and compiler messages
+    fn error(&self, text: &str) {
+        self.print(text)
+    }
+}
+
+/// Straight to stdout and stderr, like daScript's own printer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdOutput;
+
+impl VMOutput for StdOutput {
+    fn print(&self, text: &str) {
+        let mut out = std::io::stdout().lock();
+        let _ = out.write_all(text.as_bytes());
+        let _ = out.flush();
+    }
+
+    fn error(&self, text: &str) {
+        let _ = std::io::stderr().write_all(text.as_bytes());
+    }
+}
+
+/// Through the `log` crate, prints as `info` and errors as `error`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogOutput;
+
+impl VMOutput for LogOutput {
+    fn print(&self, text: &str) {
+        info!("{}", text.trim_end());
+    }
+
+    fn error(&self, text: &str) {
+        error!("{}", text.trim_end());
+    }
+}
+
+/// Collects everything into a shared buffer, clones see the same text
+#[derive(Debug, Clone, Default)]
+pub struct BufferOutput(Arc<Mutex<String>>);
+
+impl BufferOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contents(&self) -> String {
+        self.0.lock().clone()
+    }
+
+    /// Hand back the collected text and start over
+    pub fn take(&self) -> String {
+        std::mem::take(&mut *self.0.lock())
+    }
+}
+
+impl VMOutput for BufferOutput {
+    fn print(&self, text: &str) {
+        self.0.lock().push_str(text);
+    }
+}
+
+impl<F: Fn(&str) + Send + Sync> VMOutput for F {
+    fn print(&self, text: &str) {
+        self(text)
+    }
+}
+
+pub(crate) type SharedOutput = Arc<dyn VMOutput>;
+
+/// A sink pinned on the heap, native writers and contexts keep a thin pointer to it.
+/// Must outlive whatever was made from it.
+pub(crate) struct OutputHandle(Box<SharedOutput>);
+
+impl OutputHandle {
+    pub(crate) fn new(output: SharedOutput) -> Self {
+        Self(Box::new(output))
+    }
+
+    fn user(&self) -> *mut c_void {
+        (&*self.0 as *const SharedOutput).cast_mut().cast()
+    }
+
+    /// A text writer for compiler and simulation messages
+    pub(crate) unsafe fn make_writer(&self) -> *mut das_text_writer {
+        dasx_text_make_callback(self.user(), Some(write_output))
+    }
+
+    /// A context whose `print` and errors come here
+    pub(crate) unsafe fn make_context(&self, stack_size: i32) -> *mut das_context {
+        dasx_context_make_with_output(stack_size, self.user(), Some(write_output))
+    }
+}
+
+unsafe extern "C" fn write_output(
+    user: *mut c_void,
+    text: *const c_char,
+    length: c_int,
+    is_error: c_int,
+) {
+    if user.is_null() || text.is_null() || length <= 0 {
+        return;
+    }
+    let output = &*(user as *const SharedOutput);
+    let text = String::from_utf8_lossy(slice::from_raw_parts(text.cast(), length as usize));
+    if is_error != 0 {
+        output.error(&text);
+    } else {
+        output.print(&text);
+    }
+}
+
+/// Per-context layer over the engine sink, diverts prints into a buffer during a captured call
+pub(crate) struct ContextOutput {
+    sink: SharedOutput,
+    capture: Mutex<Option<String>>,
+}
+
+impl ContextOutput {
+    pub(crate) fn new(sink: SharedOutput) -> Self {
+        Self {
+            sink,
+            capture: Mutex::new(None),
+        }
+    }
+
+    /// Start capturing, hands back an outer capture to restore with `end`
+    pub(crate) fn begin(&self) -> Option<String> {
+        self.capture.lock().replace(String::new())
+    }
+
+    /// Stop capturing and return what was printed since `begin`
+    pub(crate) fn end(&self, outer: Option<String>) -> String {
+        let mut capture = self.capture.lock();
+        let text = capture.take().unwrap_or_default();
+        *capture = outer;
+        text
+    }
+
+    /// Pass captured text on as if it was never captured
+    pub(crate) fn forward(&self, text: &str) {
+        if !text.is_empty() {
+            self.print(text);
+        }
+    }
+}
+
+impl VMOutput for ContextOutput {
+    fn print(&self, text: &str) {
+        if let Some(buffer) = self.capture.lock().as_mut() {
+            buffer.push_str(text);
+            return;
+        }
+        self.sink.print(text);
+    }
+
+    fn error(&self, text: &str) {
+        self.sink.error(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps prints and errors apart so tests can tell where text went
+    #[derive(Default)]
+    struct Recorder {
+        printed: Mutex<String>,
+        errors: Mutex<String>,
+    }
+
+    impl VMOutput for Recorder {
+        fn print(&self, text: &str) {
+            self.printed.lock().push_str(text);
+        }
+
+        fn error(&self, text: &str) {
+            self.errors.lock().push_str(text);
+        }
+    }
+
+    fn layered() -> (Arc<Recorder>, ContextOutput) {
+        let sink = Arc::new(Recorder::default());
+        let output = ContextOutput::new(sink.clone());
+        (sink, output)
+    }
+
+    #[test]
+    fn prints_reach_the_sink_without_capture() {
+        let (sink, output) = layered();
+        output.print("hello\n");
+        assert_eq!(*sink.printed.lock(), "hello\n");
+    }
+
+    #[test]
+    fn nested_capture_restores_the_outer_buffer() {
+        let (sink, output) = layered();
+        let outer = output.begin();
+        assert_eq!(outer, None);
+        output.print("a");
+
+        let inner = output.begin();
+        assert_eq!(inner.as_deref(), Some("a"));
+        output.print("b");
+        assert_eq!(output.end(inner), "b");
+
+        output.print("c");
+        assert_eq!(output.end(outer), "ac");
+        assert_eq!(*sink.printed.lock(), "");
+
+        output.print("d");
+        assert_eq!(*sink.printed.lock(), "d");
+    }
+
+    #[test]
+    fn errors_bypass_the_capture() {
+        let (sink, output) = layered();
+        let outer = output.begin();
+        output.print("kept");
+        output.error("failed\n");
+        assert_eq!(*sink.errors.lock(), "failed\n");
+        assert_eq!(output.end(outer), "kept");
+        assert_eq!(*sink.printed.lock(), "");
+    }
+
+    #[test]
+    fn forward_hands_text_to_the_enclosing_layer() {
+        let (sink, output) = layered();
+        let outer = output.begin();
+        let inner = output.begin();
+        output.print("lost call");
+        let text = output.end(inner);
+        output.forward(&text);
+        assert_eq!(*sink.printed.lock(), "");
+        assert_eq!(output.end(outer), "lost call");
+
+        output.forward("");
+        output.forward("top level");
+        assert_eq!(*sink.printed.lock(), "top level");
+    }
+}
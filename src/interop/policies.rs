@@ -0,0 +1,84 @@
+//! Compile-time restrictions on what scripts may do
+
+use super::extended::dasx_policies;
+
+/// What the compiler accepts, a subset of daScript's code of policies.
+///
+/// Programs breaking a policy fail to load with `VMError::Compile`.
+/// `Default` matches daScript's own defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompilePolicies {
+    /// Forbid `unsafe` blocks, and with them pointer arithmetic and reinterpret casts
+    pub no_unsafe: bool,
+    /// Forbid mutable globals, `let` constants are still allowed
+    pub no_global_variables: bool,
+    /// Forbid globals altogether
+    pub no_global_variables_at_all: bool,
+    /// Forbid globals initialized on the heap
+    pub no_global_heap: bool,
+    /// Forbid `[init]` functions, which run during hosting
+    pub no_init: bool,
+    /// Reject calls where arguments may alias each other
+    pub no_aliasing: bool,
+    /// Reject deprecated functions and syntax
+    pub no_deprecated: bool,
+    /// Require `unsafe` to take smart pointers apart
+    pub strict_smart_pointers: bool,
+    /// Require `unsafe` around `delete`
+    pub strict_unsafe_delete: bool,
+    /// Allow `tab[key]` lookups, which insert missing keys, outside of `unsafe`
+    pub unsafe_table_lookup: bool,
+}
+
+impl Default for CompilePolicies {
+    fn default() -> Self {
+        Self {
+            no_unsafe: false,
+            no_global_variables: false,
+            no_global_variables_at_all: false,
+            no_global_heap: false,
+            no_init: false,
+            no_aliasing: false,
+            no_deprecated: false,
+            strict_smart_pointers: false,
+            strict_unsafe_delete: false,
+            unsafe_table_lookup: true,
+        }
+    }
+}
+
+impl CompilePolicies {
+    /// For untrusted scripts: no `unsafe`, no mutable or heap globals, no `[init]`, strict checks.
+    ///
+    /// Policies alone do not stop `require fio`, pair with `VMFileAccess::Sandboxed`
+    /// which refuses native modules the engine did not register.
+    pub fn sandbox() -> Self {
+        Self {
+            no_unsafe: true,
+            no_global_variables: true,
+            no_global_variables_at_all: false,
+            no_global_heap: true,
+            no_init: true,
+            no_aliasing: true,
+            no_deprecated: true,
+            strict_smart_pointers: true,
+            strict_unsafe_delete: true,
+            unsafe_table_lookup: false,
+        }
+    }
+
+    pub(crate) fn raw(&self) -> dasx_policies {
+        dasx_policies {
+            no_unsafe: self.no_unsafe,
+            no_global_variables: self.no_global_variables,
+            no_global_variables_at_all: self.no_global_variables_at_all,
+            no_global_heap: self.no_global_heap,
+            no_init: self.no_init,
+            no_aliasing: self.no_aliasing,
+            no_deprecated: self.no_deprecated,
+            strict_smart_pointers: self.strict_smart_pointers,
+            strict_unsafe_delete: self.strict_unsafe_delete,
+            unsafe_table_lookup: self.unsafe_table_lookup,
+        }
+    }
+}
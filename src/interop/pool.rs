@@ -0,0 +1,129 @@
+//! Pools of pre-simulated contexts
+
+use super::{
+    extended::dasx_context_reset, runtime, VMContext, VMHangedLock, VMHostOptions, VMProgram,
+    VMResult,
+};
+use log::debug;
+use parking_lot::Mutex;
+use std::{ops::Deref, sync::Arc};
+
+/// Contexts of one program simulated ahead of time and handed out as leases.
+///
+/// A returned context is reset to its state right after simulation, globals included.
+/// Contexts the engine released (teardown, unload) are dropped from the pool,
+/// and re-hosted ones keep being pooled after a reload.
+pub struct VMPool {
+    program: Arc<VMProgram>,
+    options: VMHostOptions,
+    /// How many idle contexts are kept, leases beyond it host a fresh one
+    capacity: usize,
+    idle: Mutex<Vec<VMHangedLock<VMContext>>>,
+}
+
+impl VMProgram {
+    /// Pre-simulate `n` contexts to lease out
+    pub fn pool(self: &Arc<Self>, n: usize) -> VMResult<VMPool> {
+        self.pool_with(n, VMHostOptions::default())
+    }
+
+    /// Like `pool`, hosting every context with `options`
+    pub fn pool_with(self: &Arc<Self>, n: usize, options: VMHostOptions) -> VMResult<VMPool> {
+        debug!("VM: Pooling {} contexts of {}", n, self.path);
+        let idle = (0..n)
+            .map(|_| self.host_with(options))
+            .collect::<VMResult<Vec<_>>>()?;
+
+        Ok(VMPool {
+            program: self.clone(),
+            options,
+            capacity: n,
+            idle: Mutex::new(idle),
+        })
+    }
+}
+
+impl VMPool {
+    /// Take an idle context, or host a fresh one when all are leased
+    pub fn lease(&self) -> VMResult<VMLease<'_>> {
+        let pooled = {
+            let mut idle = self.idle.lock();
+            // drop whatever the engine released while idle
+            idle.retain(|ctx| ctx.is_alive());
+            idle.pop()
+        };
+        let ctx = match pooled {
+            Some(ctx) => ctx,
+            None => self.program.host_with(self.options)?,
+        };
+        Ok(VMLease {
+            pool: self,
+            ctx: Some(ctx),
+        })
+    }
+
+    /// Contexts ready to be leased without simulating
+    pub fn idle(&self) -> usize {
+        self.idle.lock().len()
+    }
+
+    pub fn program(&self) -> &Arc<VMProgram> {
+        &self.program
+    }
+
+    fn give_back(&self, ctx: VMHangedLock<VMContext>) {
+        // a clone kept past the lease would see the next lessee's calls
+        if Arc::strong_count(&ctx.0) > 1 {
+            debug!("VM: Leased context is still referenced, not pooling it");
+            return;
+        }
+        let reset = match ctx.0.write().hanged.as_deref_mut() {
+            Some(c) => {
+                // the last lessee's cancel handles and budget must not reach the next one
+                c.renew_interrupt(self.options.time_budget);
+                unsafe { c.reset() }
+            }
+            None => false,
+        };
+        if !reset {
+            debug!("VM: Leased context could not be reset, dropping it");
+            return;
+        }
+
+        let mut idle = self.idle.lock();
+        if idle.len() < self.capacity {
+            idle.push(ctx);
+        }
+    }
+}
+
+/// A context leased from a `VMPool`, reset and returned when dropped
+pub struct VMLease<'a> {
+    pool: &'a VMPool,
+    ctx: Option<VMHangedLock<VMContext>>,
+}
+
+impl Deref for VMLease<'_> {
+    type Target = VMHangedLock<VMContext>;
+
+    fn deref(&self) -> &Self::Target {
+        self.ctx.as_ref().expect("lease is only emptied on drop")
+    }
+}
+
+impl Drop for VMLease<'_> {
+    fn drop(&mut self) {
+        if let Some(ctx) = self.ctx.take() {
+            self.pool.give_back(ctx);
+        }
+    }
+}
+
+impl VMContext {
+    /// Back to the state right after simulation, `false` if the init script failed
+    unsafe fn reset(&self) -> bool {
+        debug!("VM: Resetting context");
+        runtime::bind();
+        dasx_context_reset(self.context) != 0
+    }
+}
@@ -0,0 +1,184 @@
+//! Hot reload of loaded programs
+//!
+//! Sources are found by following `require` lines the way the default file
+//! access resolves them, a `.das_project` with custom remapping may track less.
+
+use super::{VMContext, VMEngine, VMError, VMFileAccess, VMResult};
+use log::debug;
+use std::sync::Arc;
+use std::{collections::HashMap, ffi::CString, fs, time::SystemTime};
+
+/// A file a program was compiled from, with its modification time at compile
+pub(crate) struct SourceStamp {
+    path: String,
+    /// `None` for files that only exist in memory, those never go stale
+    modified: Option<SystemTime>,
+}
+
+impl SourceStamp {
+    fn is_stale(&self) -> bool {
+        self.modified.is_some() && modified(&self.path) != self.modified
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Module names of every `require` in `code`
+fn requires(code: &str) -> impl Iterator<Item = &str> {
+    code.lines()
+        .filter_map(|line| line.trim_start().strip_prefix("require "))
+        .filter_map(|rest| rest.split_whitespace().next())
+}
+
+/// `require a.b` from `dir/main.das` reads `dir/a/b.das`
+fn resolve(from: &str, module: &str) -> String {
+    let dir = from.rfind(['/', '\\']).map_or("", |i| &from[..=i]);
+    format!("{}{}.das", dir, module.replace('.', "/"))
+}
+
+/// Collect `path` and every file it transitively `require`s.
+/// Only files `access` would read from disk are read, and stamped, here.
+pub(crate) fn scan_sources(
+    path: &str,
+    in_memory: &HashMap<String, Arc<CString>>,
+    access: &VMFileAccess,
+) -> Vec<SourceStamp> {
+    let mut stamps: Vec<SourceStamp> = Vec::new();
+    let mut pending = vec![path.to_string()];
+
+    while let Some(file) = pending.pop() {
+        if stamps.iter().any(|s| s.path == file) {
+            continue;
+        }
+        let on_disk = access.reads_disk(&file);
+        let code = match in_memory.get(&file) {
+            Some(code) => Some(code.to_string_lossy().into_owned()),
+            None if on_disk => fs::read_to_string(&file).ok(),
+            None => None,
+        };
+        // builtin and daslib modules resolve to nothing here and are skipped
+        let Some(code) = code else {
+            continue;
+        };
+
+        pending.extend(requires(&code).map(|module| resolve(&file, module)));
+        stamps.push(SourceStamp {
+            modified: if on_disk { modified(&file) } else { None },
+            path: file,
+        });
+    }
+    stamps
+}
+
+impl VMEngine {
+    /// Loaded programs with a source changed on disk since they were compiled
+    pub fn changed(&self) -> Vec<String> {
+        self.sys_progs
+            .iter()
+            .filter(|(_, prog)| prog.compiled.read().sources.iter().any(|s| s.is_stale()))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Reload every changed program, reporting each outcome
+    pub fn reload_changed(&mut self) -> Vec<(String, VMResult<()>)> {
+        self.changed()
+            .into_iter()
+            .map(|path| {
+                let result = self.reload(&path);
+                (path, result)
+            })
+            .collect()
+    }
+
+    /// Recompile `path` and re-host every live context simulated from it.
+    ///
+    /// On failure nothing changes and the old program keeps running.
+    /// Re-hosted contexts start from a fresh simulation, script globals are not carried over.
+    /// In-memory programs reload whatever was last `introduce`d.
+    pub fn reload(&mut self, path: &str) -> VMResult<()> {
+        let prog = self
+            .sys_progs
+            .get(path)
+            .cloned()
+            .ok_or_else(|| VMError::ProgramNotFound(path.to_string()))?;
+
+        // the current file access caches what it read, a fresh one reads changed files anew
+        let files = unsafe { self.fork_files(&[])? };
+        let compiled = Arc::new(unsafe { self.compile(path, &prog.policies, files.clone())? });
+        let old = prog.compiled.read().clone();
+
+        let hosted = self.state.read().hosted(&old);
+
+        debug!("VM: Re-hosting {} contexts of {}", hosted.len(), path);
+        let (output, stack_size) = {
+            let sref = self.state.read();
+            (sref.output.clone(), sref.stack_size)
+        };
+        let mut fresh = Vec::with_capacity(hosted.len());
+        for ctx in &hosted {
+            let options = ctx.with(|c| Ok(c.options)).unwrap_or_default();
+            // on error the new contexts and program are dropped, the old ones stay
+            fresh.push(unsafe {
+                VMContext::simulate(compiled.clone(), output.clone(), options, stack_size)?
+            });
+        }
+
+        for (ctx, mut new) in hosted.iter().zip(fresh) {
+            let mut wref = ctx.0.write();
+            if let Some(old) = wref.hanged.as_deref() {
+                new.take_over(old);
+                new.generation = old.generation + 1;
+            }
+            wref.hanged = Some(Box::new(new));
+        }
+        *prog.compiled.write() = compiled;
+        // later compiles see the new content too
+        *self.core.files.lock() = files;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{requires, resolve};
+
+    #[test]
+    fn requires_lists_each_module() {
+        let code = "options gen2\nrequire math\n  require lib.util public\n// require commented\nrequire sub/other as o\n";
+        assert_eq!(
+            requires(code).collect::<Vec<_>>(),
+            ["math", "lib.util", "sub/other"]
+        );
+    }
+
+    #[test]
+    fn requires_skips_lookalikes() {
+        assert_eq!(requires("def required()\n    pass\n").count(), 0);
+        assert_eq!(requires("let s = \"require x\"\n").count(), 0);
+    }
+
+    #[test]
+    fn resolve_is_relative_to_the_requiring_file() {
+        assert_eq!(resolve("scripts/main.das", "util"), "scripts/util.das");
+        assert_eq!(resolve("main.das", "util"), "util.das");
+        assert_eq!(
+            resolve("C:\\scripts\\main.das", "util"),
+            "C:\\scripts\\util.das"
+        );
+    }
+
+    #[test]
+    fn resolve_maps_dots_to_directories() {
+        assert_eq!(
+            resolve("scripts/main.das", "lib.util"),
+            "scripts/lib/util.das"
+        );
+        assert_eq!(
+            resolve("scripts/main.das", "lib/util"),
+            "scripts/lib/util.das"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! The process-wide daScript runtime
+//!
+//! `das_initialize` and `das_shutdown` act on global state shared by every
+//! engine, so they are counted: the first holder initializes, the last one
+//! shuts down.
+//!
+//! daScript finds that state through thread locals set by `das_initialize`.
+//! Other threads bind it before calling in, and whatever mutates it (compiling,
+//! registering modules, simulating, releasing) runs under one process-wide lock.
+
+use super::extended::{dasx_env_adopt, dasx_env_bind, dasx_env_clear, dasx_env_current};
+use crate::bindings::das::{das_initialize, das_shutdown};
+use log::{debug, info};
+use parking_lot::{const_mutex, const_reentrant_mutex, Mutex, ReentrantMutex, ReentrantMutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static LIVE: Mutex<usize> = const_mutex(0);
+/// The environment `das_initialize` created, 0 while shut down
+static ENV: AtomicUsize = AtomicUsize::new(0);
+/// Reentrant, releasing a context while simulating another must not deadlock
+static ENV_LOCK: ReentrantMutex<()> = const_reentrant_mutex(());
+
+fn acquire() {
+    let mut live = LIVE.lock();
+    if *live == 0 {
+        info!("VM: Initializing runtime");
+        let _env = ENV_LOCK.lock();
+        unsafe {
+            // a previous runtime may have been bound here, das_initialize would reuse it
+            dasx_env_clear();
+            das_initialize();
+            ENV.store(dasx_env_current() as usize, Ordering::Release);
+        }
+    }
+    *live += 1;
+}
+
+fn release() {
+    let mut live = LIVE.lock();
+    match *live {
+        0 => debug!("VM: Runtime released more often than acquired"),
+        1 => {
+            info!("VM: Shutting down runtime");
+            *live = 0;
+            let _env = ENV_LOCK.lock();
+            unsafe {
+                // the last holder may be a worker thread that only bound it
+                dasx_env_adopt(ENV.load(Ordering::Acquire) as *mut _);
+                das_shutdown();
+                dasx_env_clear();
+            }
+            ENV.store(0, Ordering::Release);
+        }
+        n => *live = n - 1,
+    }
+}
+
+/// Bind the runtime's environment on this thread, enough for evaluating in a context
+pub(crate) fn bind() {
+    let env = ENV.load(Ordering::Acquire);
+    if env != 0 {
+        unsafe {
+            dasx_env_bind(env as *mut _);
+        }
+    }
+}
+
+/// Bind the environment and keep other threads from changing it until the guard drops
+pub(crate) fn environment() -> ReentrantMutexGuard<'static, ()> {
+    let guard = ENV_LOCK.lock();
+    bind();
+    guard
+}
+
+/// Keeps the daScript runtime initialized while alive
+pub struct VMRuntime(());
+
+impl VMRuntime {
+    pub fn acquire() -> Self {
+        acquire();
+        VMRuntime(())
+    }
+
+    /// Number of holders, engines and `engine_initialize` calls alike
+    pub fn live() -> usize {
+        *LIVE.lock()
+    }
+}
+
+impl Drop for VMRuntime {
+    fn drop(&mut self) {
+        release();
+    }
+}
+
+#[no_mangle]
+/// Initialize daScript runtime, pair every call with `engine_shutdown`
+pub extern "C" fn engine_initialize() {
+    acquire();
+}
+
+#[no_mangle]
+/// Shutdown daScript runtime, once no engine or other caller holds it
+pub extern "C" fn engine_shutdown() {
+    release();
+}
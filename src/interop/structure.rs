@@ -0,0 +1,169 @@
+//! Rust structures and enums shared with scripts
+//!
+//! daScript sees structures as handled types, scripts hold a `Name?` the host
+//! passes in and read or write fields in place. Enumerations travel as `int`.
+
+use std::ffi::c_void;
+
+/// A field type with a daScript equivalent of the same size
+pub trait DasFieldType {
+    /// daScript mangled type name
+    const MANGLED: &'static str;
+}
+
+macro_rules! das_field {
+    ($($ty:ty => $mangled:literal),* $(,)?) => {
+        $(
+            impl DasFieldType for $ty {
+                const MANGLED: &'static str = $mangled;
+            }
+        )*
+    };
+}
+
+das_field! {
+    bool => "b",
+    i8 => "i8",
+    u8 => "u8",
+    i16 => "i16",
+    u16 => "u16",
+    i32 => "i",
+    u32 => "u",
+    i64 => "i64",
+    u64 => "u64",
+    f32 => "f",
+    f64 => "d",
+    *mut c_void => "?",
+    *const c_void => "?",
+}
+
+/// One field as daScript sees it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DasField {
+    pub name: &'static str,
+    pub offset: usize,
+    pub mangled: &'static str,
+}
+
+/// A `#[repr(C)]` struct scripts can address by pointer, usually derived.
+///
+/// The derive refuses structs whose layout Rust is free to reorder:
+///
+/// ```compile_fail
+/// use dastrap::interop::DasStructure;
+///
+/// #[derive(DasStructure)]
+/// struct Loose {
+///     value: i32,
+/// }
+/// ```
+///
+/// # Safety
+/// `fields` must report the real offsets and types of `Self`,
+/// daScript reads and writes through them without checking.
+pub unsafe trait DasStructure: Sized {
+    /// Name on the script side
+    const NAME: &'static str;
+
+    fn fields() -> Vec<DasField>;
+}
+
+/// A fieldless enum shared with scripts as an `int` based enumeration, usually derived.
+///
+/// # Safety
+/// `values` must list every variant with its real discriminant.
+pub unsafe trait DasEnumeration: Sized {
+    /// Name on the script side
+    const NAME: &'static str;
+
+    /// Mangled name of the bound enumeration, `E<NAME>`
+    const MANGLED: &'static str;
+
+    fn values() -> Vec<(&'static str, i32)>;
+
+    fn to_int(self) -> i32;
+
+    fn from_int(value: i32) -> Option<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interop::{DasEnumeration, DasField, DasStructure};
+    use std::mem::offset_of;
+
+    #[repr(C)]
+    #[derive(DasStructure)]
+    #[das(name = "Padded")]
+    #[allow(dead_code)]
+    struct Padded {
+        flag: bool,
+        value: i64,
+        #[das(skip)]
+        cache: u16,
+        small: u8,
+    }
+
+    #[test]
+    fn fields_follow_the_c_layout() {
+        assert_eq!(Padded::NAME, "Padded");
+        assert_eq!(
+            Padded::fields(),
+            [
+                DasField {
+                    name: "flag",
+                    offset: offset_of!(Padded, flag),
+                    mangled: "b",
+                },
+                DasField {
+                    name: "value",
+                    offset: offset_of!(Padded, value),
+                    mangled: "i64",
+                },
+                DasField {
+                    name: "small",
+                    offset: offset_of!(Padded, small),
+                    mangled: "u8",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn padding_and_skipped_fields_keep_their_room() {
+        // the bool is padded out to the i64, the skipped u16 still sits before `small`
+        assert_eq!(offset_of!(Padded, value), 8);
+        assert_eq!(offset_of!(Padded, small), 18);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, DasEnumeration)]
+    #[das(name = "Weather")]
+    enum Sky {
+        Clear,
+        Cloudy = 5,
+        Storm,
+        Below = -3,
+    }
+
+    #[test]
+    fn values_list_every_discriminant() {
+        assert_eq!(Sky::NAME, "Weather");
+        assert_eq!(Sky::MANGLED, "E<Weather>");
+        assert_eq!(
+            Sky::values(),
+            [("Clear", 0), ("Cloudy", 5), ("Storm", 6), ("Below", -3)]
+        );
+    }
+
+    #[test]
+    fn ints_round_trip() {
+        for sky in [Sky::Clear, Sky::Cloudy, Sky::Storm, Sky::Below] {
+            assert_eq!(Sky::from_int(sky.to_int()), Some(sky));
+        }
+    }
+
+    #[test]
+    fn unknown_ints_are_none() {
+        assert_eq!(Sky::from_int(1), None);
+        assert_eq!(Sky::from_int(i32::MAX), None);
+    }
+}
@@ -0,0 +1,306 @@
+//! Values crossing the script boundary
+//!
+//! Each argument occupies one `vec4f` slot, written the same way daScript
+//! writes results (`das_result_*_unaligned`), so the callee reads it back with
+//! the matching `das_argument_*`. Results go the other way round.
+
+use super::extended::dasx_function_signature;
+use crate::bindings::das::das_function;
+use crate::bindings::das::{
+    das_argument_double_unaligned, das_argument_float_unaligned, das_argument_int_unaligned,
+    das_argument_ptr_unaligned, das_argument_string_unaligned, das_result_double_unaligned,
+    das_result_float_unaligned, das_result_int_unaligned, das_result_ptr_unaligned,
+    das_result_string_unaligned, vec4f_unaligned, V4FloatUnlined,
+};
+use std::ffi::{c_int, c_void, CStr, CString, NulError};
+
+/// A typed argument for a script function call
+#[derive(Debug, Clone, Copy)]
+pub enum VMArg<'a> {
+    Int(i32),
+    Float(f32),
+    Double(f64),
+    String(&'a str),
+    Ptr(*mut c_void),
+}
+
+impl From<i32> for VMArg<'_> {
+    fn from(v: i32) -> Self {
+        VMArg::Int(v)
+    }
+}
+
+impl From<f32> for VMArg<'_> {
+    fn from(v: f32) -> Self {
+        VMArg::Float(v)
+    }
+}
+
+impl From<f64> for VMArg<'_> {
+    fn from(v: f64) -> Self {
+        VMArg::Double(v)
+    }
+}
+
+impl<'a> From<&'a str> for VMArg<'a> {
+    fn from(v: &'a str) -> Self {
+        VMArg::String(v)
+    }
+}
+
+impl From<*mut c_void> for VMArg<'_> {
+    fn from(v: *mut c_void) -> Self {
+        VMArg::Ptr(v)
+    }
+}
+
+impl VMArg<'_> {
+    /// The type a function has to declare to read this argument back
+    pub(crate) fn ty(&self) -> VMType {
+        match self {
+            VMArg::Int(_) => VMType::Int,
+            VMArg::Float(_) => VMType::Float,
+            VMArg::Double(_) => VMType::Double,
+            VMArg::String(_) => VMType::String,
+            VMArg::Ptr(_) => VMType::Ptr,
+        }
+    }
+}
+
+/// Marshalled argument slots, keeps string storage alive for the call
+pub(crate) struct VMArgs {
+    slots: Vec<V4FloatUnlined>,
+    _strings: Vec<CString>,
+}
+
+impl VMArgs {
+    pub(crate) fn new(args: &[VMArg]) -> Result<Self, NulError> {
+        let mut slots = Vec::with_capacity(args.len());
+        let mut strings = Vec::new();
+
+        for arg in args {
+            let mut slot = V4FloatUnlined::default();
+            unsafe {
+                match *arg {
+                    VMArg::Int(v) => das_result_int_unaligned(slot.raw(), v),
+                    VMArg::Float(v) => das_result_float_unaligned(slot.raw(), v),
+                    VMArg::Double(v) => das_result_double_unaligned(slot.raw(), v),
+                    VMArg::String(v) => {
+                        let c_str = CString::new(v)?;
+                        // the heap buffer does not move when the CString does
+                        das_result_string_unaligned(slot.raw(), c_str.as_ptr().cast_mut());
+                        strings.push(c_str);
+                    }
+                    VMArg::Ptr(v) => das_result_ptr_unaligned(slot.raw(), v),
+                }
+            }
+            slots.push(slot);
+        }
+
+        Ok(Self {
+            slots,
+            _strings: strings,
+        })
+    }
+
+    pub(crate) fn len(&self) -> i32 {
+        self.slots.len() as i32
+    }
+
+    /// Pointer to the first slot, daScript accepts a dangling one for zero arguments
+    pub(crate) unsafe fn raw(&mut self) -> *mut vec4f_unaligned {
+        self.slots.as_mut_ptr().cast()
+    }
+}
+
+/// Expected result type of a script function call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VMType {
+    Void,
+    Int,
+    Float,
+    Double,
+    String,
+    Ptr,
+}
+
+impl VMType {
+    /// From the shim's slot kind, `None` for types no `VMType` stands for
+    fn from_kind(kind: c_int) -> Option<Self> {
+        match kind {
+            0 => Some(VMType::Void),
+            1 => Some(VMType::Int),
+            2 => Some(VMType::Float),
+            3 => Some(VMType::Double),
+            4 => Some(VMType::String),
+            5 => Some(VMType::Ptr),
+            _ => None,
+        }
+    }
+}
+
+/// What a script function declares, as far as `VMType` can tell
+pub(crate) struct VMSignature {
+    result: Option<VMType>,
+    args: Vec<Option<VMType>>,
+}
+
+impl VMSignature {
+    /// `None` if the function carries no debug info to read it from
+    pub(crate) unsafe fn of(function: *mut das_function) -> Option<Self> {
+        let mut result = -1;
+        let count = dasx_function_signature(function, &mut result, std::ptr::null_mut(), 0);
+        let mut args = vec![-1; usize::try_from(count).ok()?];
+        dasx_function_signature(function, &mut result, args.as_mut_ptr(), count);
+        Some(Self {
+            result: VMType::from_kind(result),
+            args: args.into_iter().map(VMType::from_kind).collect(),
+        })
+    }
+
+    /// Why the function would misread `args`, the callee reads as many as it declares
+    pub(crate) fn check_args(&self, args: &[VMArg]) -> Result<(), String> {
+        if self.args.len() != args.len() {
+            return Err(format!(
+                "takes {} arguments, {} given",
+                self.args.len(),
+                args.len()
+            ));
+        }
+        for (i, (declared, arg)) in self.args.iter().zip(args).enumerate() {
+            if *declared != Some(arg.ty()) {
+                return Err(format!("argument {} is not {:?}", i + 1, arg.ty()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Why decoding the result as `ret` would misread it, anything goes for `VMType::Void`
+    pub(crate) fn check_result(&self, ret: VMType) -> Result<(), String> {
+        if ret == VMType::Void || self.result == Some(ret) {
+            Ok(())
+        } else {
+            Err(format!("does not return {:?}", ret))
+        }
+    }
+}
+
+/// A typed result decoded from a script function call
+#[derive(Debug, Clone, PartialEq)]
+pub enum VMValue {
+    Void,
+    Int(i32),
+    Float(f32),
+    Double(f64),
+    /// daScript represents the empty string as null, which decodes to `""`
+    String(String),
+    Ptr(*mut c_void),
+}
+
+impl VMValue {
+    /// Decode a result slot the same way daScript reads an argument
+    pub(crate) unsafe fn decode(slot: &mut V4FloatUnlined, ty: VMType) -> Self {
+        match ty {
+            VMType::Void => VMValue::Void,
+            VMType::Int => VMValue::Int(das_argument_int_unaligned(slot.raw())),
+            VMType::Float => VMValue::Float(das_argument_float_unaligned(slot.raw())),
+            VMType::Double => VMValue::Double(das_argument_double_unaligned(slot.raw())),
+            VMType::String => {
+                let ptr = das_argument_string_unaligned(slot.raw());
+                if ptr.is_null() {
+                    VMValue::String(String::new())
+                } else {
+                    VMValue::String(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+                }
+            }
+            VMType::Ptr => VMValue::Ptr(das_argument_ptr_unaligned(slot.raw())),
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            VMValue::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f32> {
+        match self {
+            VMValue::Float(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_double(&self) -> Option<f64> {
+        match self {
+            VMValue::Double(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            VMValue::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_ptr(&self) -> Option<*mut c_void> {
+        match self {
+            VMValue::Ptr(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VMArg, VMSignature, VMType};
+
+    fn add() -> VMSignature {
+        VMSignature {
+            result: Some(VMType::Int),
+            args: vec![Some(VMType::Int), Some(VMType::Int)],
+        }
+    }
+
+    #[test]
+    fn accepts_matching_arguments() {
+        assert_eq!(add().check_args(&[VMArg::Int(3), VMArg::Int(4)]), Ok(()));
+    }
+
+    #[test]
+    fn refuses_a_wrong_count() {
+        assert_eq!(
+            add().check_args(&[]),
+            Err("takes 2 arguments, 0 given".to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_a_wrong_type() {
+        assert_eq!(
+            add().check_args(&[VMArg::Int(3), VMArg::String("4")]),
+            Err("argument 2 is not String".to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_types_without_a_vmtype() {
+        let signature = VMSignature {
+            result: None,
+            args: vec![None],
+        };
+        assert!(signature.check_args(&[VMArg::Int(1)]).is_err());
+    }
+
+    #[test]
+    fn checks_the_result_unless_void() {
+        assert_eq!(add().check_result(VMType::Int), Ok(()));
+        assert_eq!(add().check_result(VMType::Void), Ok(()));
+        assert_eq!(
+            add().check_result(VMType::String),
+            Err("does not return String".to_string())
+        );
+    }
+}
@@ -1,2 +1,5 @@
+// lets `#[derive(DasStructure)]` expand to `::dastrap` paths inside this crate too
+extern crate self as dastrap;
+
 pub mod bindings;
 pub mod interop;